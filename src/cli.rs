@@ -0,0 +1,198 @@
+use config::{Config, parse};
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage:
+    etclient [run] [OPTIONS] [SPEC]
+    etclient import [OPTIONS] FILE
+    etclient export [OPTIONS] [--from N] [--to N] FILE
+    etclient db [OPTIONS] (info | remove [--yes])
+
+Commands:
+    run       Sync the chain and serve JSON-RPC (the default)
+    import    Import RLP-encoded blocks from a file
+    export    Write canonical blocks RLP-encoded to a file
+    db        Show the state of the chain database, or remove it
+
+Options:
+    --config FILE            Read settings from a JSON file
+    --datadir DIR            Data directory [default: data]
+    --node-key FILE          Hex-encoded node key [default: DIR/nodekey]
+    --chain SPEC             Chain spec file [default: Ethereum Classic]
+    --light                  Only sync headers
+    --state-sync             Download the state of a recent block instead of
+                             executing all blocks before it
+    --port PORT              eth protocol port [default: 60606]
+    --les-port PORT          LES port in light mode [default: 60607]
+    --listen                 Accept incoming connections
+    --public-address IP      Address announced to peers [default: 127.0.0.1]
+    --max-peers N            Peers to keep connected [default: 25]
+    --bootnodes URLS         Comma-separated enode URLs replacing the spec's
+    --static-nodes URLS      Comma-separated enode URLs to always stay connected to
+    --trusted-nodes URLS     Comma-separated enode URLs never to ban
+    --no-rpc                 Do not serve JSON-RPC
+    --rpc-address ADDR       JSON-RPC address [default: 127.0.0.1:8545]
+    --client-id ID           Client version sent to peers
+    --log FILTER             Log filter, e.g. info [default: $RUST_LOG]
+    --yes                    Remove the chain database without asking
+";
+
+/// Flags followed by a value.
+const VALUE_FLAGS: &[&str] = &[
+    "--config", "--datadir", "--node-key", "--chain", "--port", "--les-port", "--public-address",
+    "--max-peers", "--bootnodes", "--static-nodes", "--trusted-nodes", "--rpc-address", "--client-id", "--log", "--from", "--to",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DbCommand {
+    /// Print the chain head and the progress of downloads.
+    Info,
+    /// Delete the chain database, keeping the node key and known
+    /// peers. Unless `confirmed`, the user is asked first.
+    Remove { confirmed: bool },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Run(Config),
+    Import { config: Config, path: PathBuf },
+    /// Export canonical blocks `from` to `to`, or to the head if unset.
+    Export { config: Config, path: PathBuf, from: u64, to: Option<u64> },
+    Db { config: Config, command: DbCommand },
+    Help,
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').filter(|item| item.len() > 0).map(|item| item.to_string()).collect()
+}
+
+/// Parse the command-line arguments, without the program name. If
+/// `--config` is given, the file is read first and the other flags
+/// override its settings.
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let (name, args) = match args.first().map(|arg| arg.as_str()) {
+        Some("run") | Some("import") | Some("export") | Some("db") => (args[0].as_str(), &args[1..]),
+        Some("help") | Some("--help") | Some("-h") => return Ok(Command::Help),
+        _ => ("run", args),
+    };
+
+    let mut flags: Vec<(&str, Option<String>)> = Vec::new();
+    let mut positionals = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            positionals.push(arg.clone());
+            continue;
+        }
+
+        let (flag, inline) = match arg.find('=') {
+            Some(index) => (&arg[..index], Some(arg[(index + 1)..].to_string())),
+            None => (arg.as_str(), None),
+        };
+        let value = if VALUE_FLAGS.contains(&flag) {
+            match inline {
+                Some(value) => Some(value),
+                None => Some(iter.next().cloned().ok_or(format!("{} needs a value", flag))?),
+            }
+        } else if inline.is_some() {
+            return Err(format!("{} takes no value", flag));
+        } else {
+            None
+        };
+        flags.push((flag, value));
+    }
+
+    let mut config = match flags.iter().find(|&&(flag, _)| flag == "--config") {
+        Some(&(_, Some(ref path))) => Config::from_file(path).map_err(|e| format!("{}: {}", path, e))?,
+        _ => Config::default(),
+    };
+    let mut from = 0;
+    let mut to = None;
+    let mut confirmed = false;
+    for (flag, value) in flags {
+        let value = value.unwrap_or_default();
+        match flag {
+            "--config" => (),
+            "--datadir" => config.data_dir = PathBuf::from(value),
+            "--node-key" => config.node_key = Some(PathBuf::from(value)),
+            "--chain" => config.chain = Some(PathBuf::from(value)),
+            "--light" => config.light = true,
+            "--state-sync" => config.state_sync = true,
+            "--port" => config.port = parse("port", &value)?,
+            "--les-port" => config.les_port = parse("LES port", &value)?,
+            "--listen" => config.listen = true,
+            "--public-address" => config.public_address = parse("public address", &value)?,
+            "--max-peers" => config.max_peers = parse("max peers", &value)?,
+            "--bootnodes" => config.bootnodes = split_list(&value),
+            "--static-nodes" => config.static_nodes = split_list(&value),
+            "--trusted-nodes" => config.trusted_nodes = split_list(&value),
+            "--no-rpc" => config.rpc = false,
+            "--rpc-address" => config.rpc_address = parse("RPC address", &value)?,
+            "--client-id" => config.client_id = value,
+            "--log" => config.log = Some(value),
+            "--from" if name == "export" => from = parse("block number", &value)?,
+            "--to" if name == "export" => to = Some(parse("block number", &value)?),
+            "--yes" if name == "db" => confirmed = true,
+            _ => return Err(format!("unknown option {} for {}", flag, name)),
+        }
+    }
+
+    match (name, positionals.len()) {
+        ("run", 0) => Ok(Command::Run(config)),
+        // The chain spec used to be the only argument.
+        ("run", 1) => {
+            config.chain = Some(PathBuf::from(&positionals[0]));
+            Ok(Command::Run(config))
+        },
+        ("import", 1) => Ok(Command::Import { config, path: PathBuf::from(&positionals[0]) }),
+        ("export", 1) => Ok(Command::Export { config, path: PathBuf::from(&positionals[0]), from, to }),
+        ("db", 1) => match positionals[0].as_str() {
+            "info" => Ok(Command::Db { config, command: DbCommand::Info }),
+            "remove" => Ok(Command::Db { config, command: DbCommand::Remove { confirmed } }),
+            other => Err(format!("unknown db command {}", other)),
+        },
+        _ => Err(format!("wrong number of arguments for {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        let mut expected = Config::default();
+        expected.light = true;
+        expected.port = 30303;
+        expected.bootnodes = vec!["enode://a@1.2.3.4:30303".to_string(), "enode://b@5.6.7.8:30303".to_string()];
+        expected.chain = Some(PathBuf::from("morden.json"));
+        assert_eq!(parse_args(&args("--light --port=30303 \
+                                     --bootnodes enode://a@1.2.3.4:30303,enode://b@5.6.7.8:30303 morden.json")),
+                   Ok(Command::Run(expected.clone())));
+
+        expected = Config::default();
+        expected.data_dir = PathBuf::from("/tmp/etc");
+        assert_eq!(parse_args(&args("export --datadir /tmp/etc --to 100 blocks.rlp")),
+                   Ok(Command::Export { config: expected, path: PathBuf::from("blocks.rlp"), from: 0, to: Some(100) }));
+
+        expected = Config::default();
+        expected.state_sync = true;
+        assert_eq!(parse_args(&args("--state-sync")), Ok(Command::Run(expected)));
+
+        assert!(parse_args(&args("run --to 100")).is_err());
+        assert_eq!(parse_args(&args("db remove --yes")),
+                   Ok(Command::Db { config: Config::default(), command: DbCommand::Remove { confirmed: true } }));
+        assert!(parse_args(&args("import --port")).is_err());
+        assert_eq!(parse_args(&args("help")), Ok(Command::Help));
+
+        let config = Config::from_json(r#"{ "maxPeers": 50, "rpcAddress": "0.0.0.0:8545" }"#).unwrap();
+        assert_eq!(config.max_peers, 50);
+        assert_eq!(config.rpc_address, "0.0.0.0:8545".parse().unwrap());
+        assert!(Config::from_json(r#"{ "maxpeers": 50 }"#).is_err());
+    }
+}
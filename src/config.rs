@@ -0,0 +1,178 @@
+use devp2p::DevP2PConfig;
+use serde_json;
+
+use std::fs::File;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Node settings, read from a JSON configuration file and overridden
+/// by command-line flags.
+pub struct Config {
+    pub data_dir: PathBuf,
+    /// File holding the hex-encoded node key. If unset, the key is
+    /// kept in `nodekey` in the data directory, generated on first
+    /// start.
+    pub node_key: Option<PathBuf>,
+    /// Chain spec file. The Ethereum Classic mainnet is used if unset.
+    pub chain: Option<PathBuf>,
+    pub light: bool,
+    /// Download the state of a recent block instead of executing the
+    /// blocks before it. Ignored in light mode.
+    pub state_sync: bool,
+    /// Port discovery and RLPx listen on for the eth protocol.
+    pub port: u16,
+    /// Port discovery and RLPx listen on for LES, in light mode.
+    pub les_port: u16,
+    /// Whether to accept incoming connections.
+    pub listen: bool,
+    /// Address announced to peers, e.g. the external address of a NAT.
+    pub public_address: IpAddr,
+    pub max_peers: usize,
+    /// Bootnode enode URLs. The chain spec's are used if empty.
+    pub bootnodes: Vec<String>,
+    /// Enode URLs of peers kept connected at all times and never
+    /// banned.
+    pub static_nodes: Vec<String>,
+    /// Enode URLs of peers that are never banned.
+    pub trusted_nodes: Vec<String>,
+    pub rpc: bool,
+    pub rpc_address: SocketAddr,
+    pub client_id: String,
+    /// `env_logger` filter, such as `info` or `devp2p=debug`. The
+    /// `RUST_LOG` variable is used if unset.
+    pub log: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct JSONConfig {
+    data_dir: Option<String>,
+    node_key: Option<String>,
+    chain: Option<String>,
+    light: Option<bool>,
+    state_sync: Option<bool>,
+    port: Option<u16>,
+    les_port: Option<u16>,
+    listen: Option<bool>,
+    public_address: Option<String>,
+    max_peers: Option<usize>,
+    bootnodes: Option<Vec<String>>,
+    static_nodes: Option<Vec<String>>,
+    trusted_nodes: Option<Vec<String>>,
+    rpc: Option<bool>,
+    rpc_address: Option<String>,
+    client_id: Option<String>,
+    log: Option<String>,
+}
+
+/// Parse a setting given as a string in a configuration file or on
+/// the command line.
+pub fn parse<T: FromStr>(field: &str, value: &str) -> Result<T, String> {
+    T::from_str(value).map_err(|_| format!("invalid {}: {}", field, value))
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_dir: PathBuf::from("data"),
+            node_key: None,
+            chain: None,
+            light: false,
+            state_sync: false,
+            port: 60606,
+            les_port: 60607,
+            listen: false,
+            public_address: "127.0.0.1".parse().unwrap(),
+            max_peers: 25,
+            bootnodes: Vec::new(),
+            static_nodes: Vec::new(),
+            trusted_nodes: Vec::new(),
+            rpc: true,
+            rpc_address: "127.0.0.1:8545".parse().unwrap(),
+            client_id: "etclient Rust/0.1.0".to_string(),
+            log: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let mut content = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| e.to_string())?;
+        Self::from_json(&content)
+    }
+
+    /// Read a configuration file. Settings it leaves out keep their
+    /// defaults.
+    pub fn from_json(s: &str) -> Result<Self, String> {
+        let json: JSONConfig = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        let mut config = Config::default();
+
+        if let Some(data_dir) = json.data_dir { config.data_dir = PathBuf::from(data_dir); }
+        if let Some(node_key) = json.node_key { config.node_key = Some(PathBuf::from(node_key)); }
+        if let Some(chain) = json.chain { config.chain = Some(PathBuf::from(chain)); }
+        if let Some(light) = json.light { config.light = light; }
+        if let Some(state_sync) = json.state_sync { config.state_sync = state_sync; }
+        if let Some(port) = json.port { config.port = port; }
+        if let Some(les_port) = json.les_port { config.les_port = les_port; }
+        if let Some(listen) = json.listen { config.listen = listen; }
+        if let Some(public_address) = json.public_address {
+            config.public_address = parse("publicAddress", &public_address)?;
+        }
+        if let Some(max_peers) = json.max_peers { config.max_peers = max_peers; }
+        if let Some(bootnodes) = json.bootnodes { config.bootnodes = bootnodes; }
+        if let Some(static_nodes) = json.static_nodes { config.static_nodes = static_nodes; }
+        if let Some(trusted_nodes) = json.trusted_nodes { config.trusted_nodes = trusted_nodes; }
+        if let Some(rpc) = json.rpc { config.rpc = rpc; }
+        if let Some(rpc_address) = json.rpc_address {
+            config.rpc_address = parse("rpcAddress", &rpc_address)?;
+        }
+        if let Some(client_id) = json.client_id { config.client_id = client_id; }
+        if let Some(log) = json.log { config.log = Some(log); }
+
+        Ok(config)
+    }
+
+    /// Directory of the chain database, holding headers, bodies,
+    /// receipts and state.
+    pub fn chain_data_dir(&self) -> PathBuf {
+        self.data_dir.join("chaindata")
+    }
+
+    /// Directory of the database of peers known from previous
+    /// sessions.
+    pub fn nodes_dir(&self) -> PathBuf {
+        self.data_dir.join("nodes")
+    }
+
+    /// File holding the node key.
+    pub fn node_key_path(&self) -> PathBuf {
+        self.node_key.clone().unwrap_or(self.data_dir.join("nodekey"))
+    }
+
+    /// Address the eth protocol listens on.
+    pub fn listen_address(&self) -> SocketAddr {
+        SocketAddr::new("0.0.0.0".parse().unwrap(), self.port)
+    }
+
+    /// Address the LES client listens on.
+    pub fn les_address(&self) -> SocketAddr {
+        SocketAddr::new("0.0.0.0".parse().unwrap(), self.les_port)
+    }
+
+    pub fn devp2p(&self) -> DevP2PConfig {
+        DevP2PConfig {
+            ping_interval: Duration::new(600, 0),
+            ping_timeout_interval: Duration::new(700, 0),
+            optimal_peers_len: self.max_peers,
+            optimal_peers_interval: Duration::new(5, 0),
+            reconnect_dividend: 5,
+            listen: self.listen,
+        }
+    }
+}
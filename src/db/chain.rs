@@ -0,0 +1,264 @@
+use bigint::{H256, U256};
+use block::{Header, Transaction, Receipt, RlpHash};
+use rlp::{self, RlpStream, UntrustedRlp};
+use blockchain::chain::HeaderHash;
+use std::sync::Arc;
+
+use super::{KeyValueStore, prefixed};
+
+const HEADER_PREFIX: &[u8] = b"h";
+const BODY_PREFIX: &[u8] = b"b";
+const CANONICAL_PREFIX: &[u8] = b"n";
+const RECEIPTS_PREFIX: &[u8] = b"r";
+const TRANSACTION_PREFIX: &[u8] = b"x";
+const BEST_KEY: &[u8] = b"best";
+const PIVOT_KEY: &[u8] = b"pivot";
+const RECEIPT_SYNC_KEY: &[u8] = b"receiptsync";
+
+fn number_key(number: U256) -> Vec<u8> {
+    let mut bytes = [0u8; 32];
+    number.to_big_endian(&mut bytes);
+    prefixed(CANONICAL_PREFIX, &bytes)
+}
+
+/// Blocks leaving and joining the canonical chain when its head
+/// changes, both ordered by ascending number.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChainRoute {
+    pub retracted: Vec<H256>,
+    pub enacted: Vec<H256>,
+}
+
+/// Header chain and block body storage backed by a key-value store.
+#[derive(Clone)]
+pub struct ChainDatabase(Arc<KeyValueStore>);
+
+impl ChainDatabase {
+    pub fn new(store: Arc<KeyValueStore>) -> Self {
+        ChainDatabase(store)
+    }
+
+    /// Fetch a header together with its total difficulty.
+    pub fn fetch(&self, hash: H256) -> Option<(Header, U256)> {
+        self.0.get(&prefixed(HEADER_PREFIX, hash.as_ref())).map(|raw| {
+            let rlp = UntrustedRlp::new(&raw);
+            (rlp.val_at(0).unwrap(), rlp.val_at(1).unwrap())
+        })
+    }
+
+    pub fn header(&self, hash: H256) -> Option<Header> {
+        self.fetch(hash).map(|v| v.0)
+    }
+
+    pub fn total_difficulty(&self, hash: H256) -> Option<U256> {
+        self.fetch(hash).map(|v| v.1)
+    }
+
+    pub fn put_header(&self, header: &Header, total_difficulty: U256) {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(header);
+        stream.append(&total_difficulty);
+        self.0.put(&prefixed(HEADER_PREFIX, header.header_hash().as_ref()), &stream.out());
+    }
+
+    pub fn body(&self, hash: H256) -> Option<(Vec<Transaction>, Vec<Header>)> {
+        self.0.get(&prefixed(BODY_PREFIX, hash.as_ref())).map(|raw| {
+            let rlp = UntrustedRlp::new(&raw);
+            (rlp.list_at(0).unwrap(), rlp.list_at(1).unwrap())
+        })
+    }
+
+    pub fn put_body(&self, hash: H256, transactions: &[Transaction], ommers: &[Header]) {
+        let mut stream = RlpStream::new_list(2);
+        stream.append_list(transactions);
+        stream.append_list(ommers);
+        self.0.put(&prefixed(BODY_PREFIX, hash.as_ref()), &stream.out());
+    }
+
+    pub fn receipts(&self, hash: H256) -> Option<Vec<Receipt>> {
+        self.0.get(&prefixed(RECEIPTS_PREFIX, hash.as_ref())).map(|raw| rlp::decode_list(&raw))
+    }
+
+    pub fn put_receipts(&self, hash: H256, receipts: &[Receipt]) {
+        self.0.put(&prefixed(RECEIPTS_PREFIX, hash.as_ref()), &rlp::encode_list(receipts));
+    }
+
+    /// Block hash and index of the block a transaction was included
+    /// in.
+    pub fn transaction_location(&self, hash: H256) -> Option<(H256, usize)> {
+        self.0.get(&prefixed(TRANSACTION_PREFIX, hash.as_ref())).map(|raw| {
+            let rlp = UntrustedRlp::new(&raw);
+            (rlp.val_at(0).unwrap(), rlp.val_at(1).unwrap())
+        })
+    }
+
+    /// Index all transactions of a block by their hashes.
+    pub fn put_transaction_locations(&self, hash: H256, transactions: &[Transaction]) {
+        for (index, transaction) in transactions.iter().enumerate() {
+            let mut stream = RlpStream::new_list(2);
+            stream.append(&hash);
+            stream.append(&index);
+            self.0.put(&prefixed(TRANSACTION_PREFIX, transaction.rlp_hash().as_ref()), &stream.out());
+        }
+    }
+
+    fn delete_transaction_locations(&self, transactions: &[Transaction]) {
+        for transaction in transactions {
+            self.0.delete(&prefixed(TRANSACTION_PREFIX, transaction.rlp_hash().as_ref()));
+        }
+    }
+
+    pub fn canonical_hash(&self, number: U256) -> Option<H256> {
+        self.0.get(&number_key(number)).map(|raw| rlp::decode(&raw))
+    }
+
+    pub fn set_canonical_hash(&self, number: U256, hash: H256) {
+        self.0.put(&number_key(number), &rlp::encode(&hash));
+    }
+
+    pub fn best_hash(&self) -> Option<H256> {
+        self.0.get(BEST_KEY).map(|raw| rlp::decode(&raw))
+    }
+
+    pub fn set_best_hash(&self, hash: H256) {
+        self.0.put(BEST_KEY, &rlp::encode(&hash));
+    }
+
+    /// Block whose state is being downloaded, if a state sync was
+    /// interrupted.
+    pub fn state_pivot(&self) -> Option<H256> {
+        self.0.get(PIVOT_KEY).map(|raw| rlp::decode(&raw))
+    }
+
+    pub fn set_state_pivot(&self, hash: Option<H256>) {
+        match hash {
+            Some(hash) => self.0.put(PIVOT_KEY, &rlp::encode(&hash)),
+            None => self.0.delete(PIVOT_KEY),
+        }
+    }
+
+    /// Number of the first canonical block whose receipts still have
+    /// to be downloaded, if a receipt download was started.
+    pub fn receipt_sync_next(&self) -> Option<U256> {
+        self.0.get(RECEIPT_SYNC_KEY).map(|raw| rlp::decode(&raw))
+    }
+
+    pub fn set_receipt_sync_next(&self, number: U256) {
+        self.0.put(RECEIPT_SYNC_KEY, &rlp::encode(&number));
+    }
+
+    /// Make the stored block `hash` the head of the canonical chain,
+    /// rewriting the canonical number index and the transaction index
+    /// back to the common ancestor with the previous head. State needs
+    /// no rewinding, as every block's state root stays in the trie.
+    /// Returns `None` without changing anything if the block or one of
+    /// its ancestors above the common ancestor is not stored, as below
+    /// a checkpoint.
+    pub fn set_head(&self, hash: H256) -> Option<ChainRoute> {
+        let head = self.header(hash)?;
+
+        let mut enacted = Vec::new();
+        let mut current = head.clone();
+        while self.canonical_hash(current.number) != Some(current.header_hash()) {
+            let parent_hash = match current.parent_hash() {
+                Some(parent_hash) => parent_hash,
+                None => break,
+            };
+            enacted.push(current);
+            current = self.header(parent_hash)?;
+        }
+        enacted.reverse();
+
+        let mut retracted = Vec::new();
+        let old_number = self.best_hash().and_then(|h| self.header(h))
+            .map(|h| h.number).unwrap_or(U256::zero());
+        let mut number = current.number + U256::one();
+        while number <= old_number {
+            if let Some(old_hash) = self.canonical_hash(number) {
+                if let Some((transactions, _)) = self.body(old_hash) {
+                    self.delete_transaction_locations(&transactions);
+                }
+                retracted.push(old_hash);
+            }
+            if number > head.number {
+                self.0.delete(&number_key(number));
+            }
+            number = number + U256::one();
+        }
+
+        for header in &enacted {
+            let header_hash = header.header_hash();
+            self.set_canonical_hash(header.number, header_hash);
+            if let Some((transactions, _)) = self.body(header_hash) {
+                self.put_transaction_locations(header_hash, &transactions);
+            }
+        }
+        self.set_best_hash(hash);
+
+        Some(ChainRoute {
+            retracted,
+            enacted: enacted.iter().map(|h| h.header_hash()).collect(),
+        })
+    }
+
+    /// Hashes of `len` blocks walking backwards from `from`,
+    /// inclusive.
+    pub fn last_hashes(&self, from: H256, len: usize) -> Vec<H256> {
+        let mut ret = Vec::new();
+        let mut current = self.header(from);
+
+        while ret.len() < len {
+            let header = match current {
+                Some(header) => header,
+                None => break,
+            };
+            ret.push(header.header_hash());
+
+            current = match header.parent_hash() {
+                Some(parent_hash) => self.header(parent_hash),
+                None => None,
+            };
+        }
+
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::MemoryKeyValueStore;
+    use testing::{header, child};
+
+    #[test]
+    fn reorganize() {
+        let chain = ChainDatabase::new(Arc::new(MemoryKeyValueStore::new()));
+        let genesis = header(H256::default(), U256::zero());
+        chain.put_header(&genesis, U256::zero());
+        chain.set_canonical_hash(U256::zero(), genesis.header_hash());
+        chain.set_best_hash(genesis.header_hash());
+
+        let a1 = child(&genesis, 1);
+        let a2 = child(&a1, 1);
+        let b1 = child(&genesis, 2);
+        for header in &[&a1, &a2, &b1] {
+            chain.put_header(header, header.number);
+        }
+
+        let route = chain.set_head(a2.header_hash()).unwrap();
+        assert_eq!(route.retracted, Vec::<H256>::new());
+        assert_eq!(route.enacted, vec![a1.header_hash(), a2.header_hash()]);
+
+        let route = chain.set_head(b1.header_hash()).unwrap();
+        assert_eq!(route.retracted, vec![a1.header_hash(), a2.header_hash()]);
+        assert_eq!(route.enacted, vec![b1.header_hash()]);
+        assert_eq!(chain.canonical_hash(U256::one()), Some(b1.header_hash()));
+        assert_eq!(chain.canonical_hash(U256::from(2u64)), None);
+        assert_eq!(chain.best_hash(), Some(b1.header_hash()));
+
+        let orphan = child(&child(&b1, 3), 3);
+        chain.put_header(&orphan, orphan.number);
+        assert_eq!(chain.set_head(orphan.header_hash()), None);
+        assert_eq!(chain.best_hash(), Some(b1.header_hash()));
+    }
+}
@@ -0,0 +1,56 @@
+mod rocks;
+mod state;
+mod chain;
+mod nodes;
+
+pub use self::rocks::RocksKeyValueStore;
+pub use self::state::{StateDatabase, StateDatabaseGuard};
+pub use self::chain::{ChainDatabase, ChainRoute};
+pub use self::nodes::NodeDatabase;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A raw key-value backend. Both the state trie and the header chain
+/// are stored on top of this, so swapping the backend only requires
+/// implementing this trait.
+pub trait KeyValueStore: Send + Sync {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+    fn put(&self, key: &[u8], value: &[u8]);
+    fn delete(&self, key: &[u8]);
+}
+
+/// In-memory backend, mostly useful for tests.
+pub struct MemoryKeyValueStore(Mutex<HashMap<Vec<u8>, Vec<u8>>>);
+
+impl MemoryKeyValueStore {
+    pub fn new() -> Self {
+        MemoryKeyValueStore(Mutex::new(HashMap::new()))
+    }
+}
+
+impl Default for MemoryKeyValueStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyValueStore for MemoryKeyValueStore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.lock().unwrap().get(key).map(|v| v.clone())
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) {
+        self.0.lock().unwrap().insert(key.to_vec(), value.to_vec());
+    }
+
+    fn delete(&self, key: &[u8]) {
+        self.0.lock().unwrap().remove(key);
+    }
+}
+
+fn prefixed(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut ret = prefix.to_vec();
+    ret.extend_from_slice(key);
+    ret
+}
@@ -0,0 +1,91 @@
+use devp2p::dpt::DPTNode;
+use rlp::{RlpStream, UntrustedRlp, DecoderError};
+use std::sync::Arc;
+
+use super::KeyValueStore;
+
+const NODES_KEY: &[u8] = b"nodes";
+/// Peers remembered across restarts.
+const MAX_KNOWN_NODES: usize = 64;
+
+/// Addresses of peers that proved to be on our chain in previous
+/// sessions, most recent first. They are used alongside the bootnodes
+/// to find peers quickly after a restart.
+#[derive(Clone)]
+pub struct NodeDatabase(Arc<KeyValueStore>);
+
+fn decode_node(rlp: &UntrustedRlp) -> Result<DPTNode, DecoderError> {
+    let address: String = rlp.val_at(0)?;
+    Ok(DPTNode {
+        address: address.parse().map_err(|_| DecoderError::Custom("invalid node address"))?,
+        tcp_port: rlp.val_at(1)?,
+        udp_port: rlp.val_at(2)?,
+        id: rlp.val_at(3)?,
+    })
+}
+
+impl NodeDatabase {
+    pub fn new(store: Arc<KeyValueStore>) -> Self {
+        NodeDatabase(store)
+    }
+
+    pub fn nodes(&self) -> Vec<DPTNode> {
+        match self.0.get(NODES_KEY) {
+            Some(raw) => UntrustedRlp::new(&raw).iter()
+                .filter_map(|rlp| decode_node(&rlp).ok())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Remember a node as the most recent good peer, forgetting the
+    /// oldest ones beyond `MAX_KNOWN_NODES`.
+    pub fn put_node(&self, node: &DPTNode) {
+        let mut nodes = self.nodes();
+        nodes.retain(|known| known.id != node.id);
+        nodes.insert(0, node.clone());
+        nodes.truncate(MAX_KNOWN_NODES);
+        self.set_nodes(&nodes);
+    }
+
+    fn set_nodes(&self, nodes: &[DPTNode]) {
+        let mut stream = RlpStream::new_list(nodes.len());
+        for node in nodes {
+            stream.begin_list(4);
+            stream.append(&node.address.to_string());
+            stream.append(&node.tcp_port);
+            stream.append(&node.udp_port);
+            stream.append(&node.id);
+        }
+        self.0.put(NODES_KEY, &stream.out());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigint::H512;
+    use db::MemoryKeyValueStore;
+    use std::net::IpAddr;
+
+    fn node(id: u64, address: &str) -> DPTNode {
+        let address: IpAddr = address.parse().unwrap();
+        DPTNode { address, tcp_port: 30303, udp_port: 30301, id: H512::from(id) }
+    }
+
+    #[test]
+    fn most_recent_first() {
+        let nodes = NodeDatabase::new(Arc::new(MemoryKeyValueStore::new()));
+        assert_eq!(nodes.nodes(), Vec::new());
+
+        for id in 0..(MAX_KNOWN_NODES as u64 + 1) {
+            nodes.put_node(&node(id, "10.0.0.1"));
+        }
+        nodes.put_node(&node(5, "::1"));
+        let known = nodes.nodes();
+        assert_eq!(known.len(), MAX_KNOWN_NODES);
+        assert_eq!(known[0], node(5, "::1"));
+        assert_eq!(known[1], node(MAX_KNOWN_NODES as u64, "10.0.0.1"));
+        assert!(!known.iter().any(|known| known.id == H512::from(0u64)));
+    }
+}
@@ -0,0 +1,27 @@
+use rocksdb::DB;
+use std::path::Path;
+
+use super::KeyValueStore;
+
+/// On-disk backend using RocksDB.
+pub struct RocksKeyValueStore(DB);
+
+impl RocksKeyValueStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        DB::open_default(path).map(RocksKeyValueStore).map_err(|e| e.to_string())
+    }
+}
+
+impl KeyValueStore for RocksKeyValueStore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(key).unwrap().map(|v| v.to_vec())
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) {
+        self.0.put(key, value).unwrap();
+    }
+
+    fn delete(&self, key: &[u8]) {
+        self.0.delete(key).unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+use trie::{Database, DatabaseGuard};
+use bigint::H256;
+use std::sync::Arc;
+
+use super::{KeyValueStore, prefixed};
+
+const STATE_PREFIX: &[u8] = b"s";
+
+/// Trie node and code storage backed by a key-value store.
+#[derive(Clone)]
+pub struct StateDatabase(Arc<KeyValueStore>);
+pub struct StateDatabaseGuard<'a>(&'a KeyValueStore);
+
+impl StateDatabase {
+    pub fn new(store: Arc<KeyValueStore>) -> Self {
+        StateDatabase(store)
+    }
+
+    /// Raw trie node or contract code with the given hash.
+    pub fn node(&self, hash: H256) -> Option<Vec<u8>> {
+        self.0.get(&prefixed(STATE_PREFIX, hash.as_ref()))
+    }
+
+    pub fn put_node(&self, hash: H256, data: &[u8]) {
+        self.0.put(&prefixed(STATE_PREFIX, hash.as_ref()), data);
+    }
+}
+
+impl<'a> Database<'a> for StateDatabase {
+    type Guard = StateDatabaseGuard<'a>;
+
+    fn create_guard(&'a self) -> Self::Guard {
+        StateDatabaseGuard(self.0.as_ref())
+    }
+}
+
+impl<'a> DatabaseGuard for StateDatabaseGuard<'a> {
+    fn get(&self, hash: H256) -> Option<Vec<u8>> {
+        self.0.get(&prefixed(STATE_PREFIX, hash.as_ref()))
+    }
+
+    fn set(&mut self, hash: H256, value: Vec<u8>) {
+        self.0.put(&prefixed(STATE_PREFIX, hash.as_ref()), &value);
+    }
+}
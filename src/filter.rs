@@ -0,0 +1,146 @@
+use bigint::{H256, U256, Address};
+use block::{Log, RlpHash};
+use bloom::LogsBloom;
+use db::ChainDatabase;
+
+#[derive(Clone, Debug)]
+/// Criteria for querying logs of canonical blocks.
+pub struct LogFilter {
+    pub from_block: U256,
+    pub to_block: U256,
+    /// Match logs from any of these addresses. Empty matches all.
+    pub addresses: Vec<Address>,
+    /// Topics by position. `None` matches anything at that position,
+    /// otherwise the topic must be one of the given values.
+    pub topics: Vec<Option<Vec<H256>>>,
+}
+
+#[derive(Clone, Debug)]
+/// A log together with where it was emitted.
+pub struct LocalizedLog {
+    pub log: Log,
+    pub block_hash: H256,
+    pub block_number: U256,
+    /// `None` if the block body is not stored, as after header-only or
+    /// fast sync where only the receipts are downloaded.
+    pub transaction_hash: Option<H256>,
+    pub transaction_index: usize,
+    pub log_index: usize,
+}
+
+impl LogFilter {
+    /// Whether a block with the given bloom may contain matching
+    /// logs.
+    pub fn bloom_possibly_matches(&self, bloom: &LogsBloom) -> bool {
+        if self.addresses.len() > 0 &&
+            !self.addresses.iter().any(|address| bloom.check(address))
+        {
+            return false;
+        }
+
+        for topic in &self.topics {
+            if let &Some(ref options) = topic {
+                if !options.iter().any(|option| bloom.check(option)) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    pub fn matches(&self, log: &Log) -> bool {
+        if self.addresses.len() > 0 && !self.addresses.contains(&log.address) {
+            return false;
+        }
+
+        for (i, topic) in self.topics.iter().enumerate() {
+            if let &Some(ref options) = topic {
+                match log.topics.get(i) {
+                    Some(value) if options.contains(value) => (),
+                    _ => return false,
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Collect all logs in the canonical chain matching the filter.
+/// Blocks whose header bloom rules out a match are skipped without
+/// reading their receipts.
+pub fn filter_logs(chain: &ChainDatabase, filter: &LogFilter) -> Vec<LocalizedLog> {
+    let mut ret = Vec::new();
+    let mut number = filter.from_block;
+
+    while number <= filter.to_block {
+        let hash = match chain.canonical_hash(number) {
+            Some(hash) => hash,
+            None => break,
+        };
+        let header = chain.header(hash).unwrap();
+
+        if filter.bloom_possibly_matches(&header.logs_bloom) {
+            let transactions = chain.body(hash).map(|b| b.0);
+            let receipts = chain.receipts(hash).unwrap_or(Vec::new());
+            let mut log_index = 0;
+
+            for (transaction_index, receipt) in receipts.into_iter().enumerate() {
+                for log in receipt.logs {
+                    if filter.matches(&log) {
+                        ret.push(LocalizedLog {
+                            log,
+                            block_hash: hash,
+                            block_number: number,
+                            transaction_hash: transactions.as_ref()
+                                .and_then(|transactions| transactions.get(transaction_index))
+                                .map(|transaction| transaction.rlp_hash()),
+                            transaction_index,
+                            log_index,
+                        });
+                    }
+                    log_index += 1;
+                }
+            }
+        }
+
+        number = number + U256::one();
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_matching() {
+        let address = Address::from(1u64);
+        let log = Log {
+            address,
+            topics: vec![H256::from(2u64), H256::from(3u64)],
+            data: Vec::new(),
+        };
+        let mut bloom = LogsBloom::new();
+        bloom.set(&log.address);
+        for topic in &log.topics {
+            bloom.set(topic);
+        }
+
+        let filter = LogFilter {
+            from_block: U256::zero(), to_block: U256::zero(),
+            addresses: vec![address],
+            topics: vec![None, Some(vec![H256::from(4u64), H256::from(3u64)])],
+        };
+        assert!(filter.bloom_possibly_matches(&bloom));
+        assert!(filter.matches(&log));
+
+        let filter = LogFilter {
+            topics: vec![Some(vec![H256::from(3u64)])],
+            ..filter
+        };
+        assert!(!filter.matches(&log));
+    }
+}
@@ -0,0 +1,565 @@
+mod proto;
+mod stream;
+
+pub use self::proto::{LESMessage, Status, ProofRequest, HeaderOrigin};
+pub use self::stream::{LESStream, LESSendMessage, LESReceiveMessage};
+
+use bigint::{H256, H512, M256, U256, Address};
+use block::{Header, Account, Receipt, receipts_root};
+use blockchain::chain::HeaderHash;
+use db::ChainDatabase;
+use devp2p::DevP2PConfig;
+use devp2p::dpt::DPTNode;
+use devp2p::rlpx::RLPxNode;
+use futures::future;
+use futures::{Future, Stream, Sink};
+use futures::sync::{mpsc, oneshot};
+use proof::{keccak, verify_account, verify_storage};
+use secp256k1::key::SecretKey;
+use tokio_core::reactor::{Core, Timeout};
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::thread;
+use std::time::{Instant, Duration};
+
+/// How long a server has to answer a request.
+const REQUEST_TIMEOUT: u64 = 10;
+/// How often outstanding requests are checked for timeouts.
+const TICK_INTERVAL: u64 = 1;
+/// Servers a query is sent to before giving up.
+const MAX_ATTEMPTS: usize = 3;
+/// How long a query may wait for an idle server.
+const QUERY_TIMEOUT: u64 = 30;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Data requested from LES servers. Everything except `Header` is
+/// verified against a block already in the local header chain.
+pub enum LightRequest {
+    /// The header with the given hash.
+    Header(H256),
+    Account { block: H256, address: Address },
+    Storage { block: H256, address: Address, index: H256 },
+    Code { block: H256, address: Address },
+    Receipts(H256),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LightResponse {
+    Header(Header),
+    Account(Option<Account>),
+    Storage(M256),
+    Code(Vec<u8>),
+    Receipts(Vec<Receipt>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LightError {
+    /// The block is not in the local header chain, so a response
+    /// could not be verified.
+    UnknownBlock(H256),
+    /// No LES server is connected.
+    NoServers,
+    /// No server became idle before the query's deadline.
+    TimedOut,
+    /// No server answered with a valid response.
+    Failed,
+}
+
+/// A request together with the channel its verified response is
+/// sent back on.
+pub struct LightQuery {
+    pub request: LightRequest,
+    pub reply: oneshot::Sender<Result<LightResponse, LightError>>,
+}
+
+/// A query being answered. A query still queued at its deadline
+/// fails.
+struct Attempt {
+    request: LightRequest,
+    reply: oneshot::Sender<Result<LightResponse, LightError>>,
+    attempts: usize,
+    deadline: Instant,
+}
+
+impl Attempt {
+    fn reply(self, result: Result<LightResponse, LightError>) {
+        let _ = self.reply.send(result);
+    }
+}
+
+/// What the response to an outstanding request is checked against.
+enum Stage {
+    Header(H256),
+    /// Account proof, followed by a storage proof if `index` is set,
+    /// or a code request if `code` is.
+    Proof { header: Header, address: Address, index: Option<H256>, code: bool },
+    Code { block: H256, address: Address, code_hash: H256 },
+    Receipts(Header),
+}
+
+enum Step {
+    Done(LightResponse),
+    Next(Stage),
+}
+
+struct Pending {
+    node: H512,
+    attempt: Attempt,
+    stage: Stage,
+    sent: Instant,
+}
+
+struct Server {
+    status: Status,
+    busy: bool,
+}
+
+/// LES client state machine. Like `sync::Syncer`, it consumes
+/// received messages and returns the messages to send. Queries are
+/// sent to servers one at a time per server, and every response is
+/// verified before it is returned: headers by hash, accounts and
+/// storage by Merkle proof against the local header's state root,
+/// code by its hash in the proven account and receipts against the
+/// header's receipts root. Servers sending invalid data are
+/// disconnected and the query is retried elsewhere.
+///
+/// Flow control is not tracked; keeping a single request outstanding
+/// per server stays well within the buffer servers grant.
+pub struct LightClient {
+    chain: ChainDatabase,
+    network_id: usize,
+    genesis_hash: H256,
+    servers: HashMap<H512, Server>,
+    queue: VecDeque<Attempt>,
+    pending: HashMap<u64, Pending>,
+    next_id: u64,
+    dropped: Vec<H512>,
+}
+
+impl LightClient {
+    pub fn new(chain: ChainDatabase, network_id: usize, genesis_hash: H256) -> Self {
+        LightClient {
+            chain, network_id, genesis_hash,
+            servers: HashMap::new(),
+            queue: VecDeque::new(),
+            pending: HashMap::new(),
+            next_id: 0,
+            dropped: Vec::new(),
+        }
+    }
+
+    /// Peers that should be disconnected since the last call.
+    pub fn take_disconnects(&mut self) -> Vec<H512> {
+        ::std::mem::replace(&mut self.dropped, Vec::new())
+    }
+
+    /// Queue a query. It fails right away if no server is connected.
+    pub fn query(&mut self, query: LightQuery, now: Instant) -> Vec<LESSendMessage> {
+        self.queue.push_back(Attempt {
+            request: query.request, reply: query.reply, attempts: 0,
+            deadline: now + Duration::new(QUERY_TIMEOUT, 0),
+        });
+        self.dispatch(now)
+    }
+
+    /// Retry requests that timed out on other servers, and fail queued
+    /// ones past their deadline.
+    pub fn tick(&mut self, now: Instant) -> Vec<LESSendMessage> {
+        let queue = ::std::mem::replace(&mut self.queue, VecDeque::new());
+        let (expired, queue): (Vec<Attempt>, VecDeque<Attempt>) =
+            queue.into_iter().partition(|attempt| now >= attempt.deadline);
+        self.queue = queue;
+        for attempt in expired {
+            attempt.reply(Err(LightError::TimedOut));
+        }
+
+        let expired: Vec<u64> = self.pending.iter()
+            .filter(|&(_, pending)| now >= pending.sent + Duration::new(REQUEST_TIMEOUT, 0))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            let pending = self.pending.remove(&id).unwrap();
+            debug!("LES request {} to {} timed out", id, pending.node);
+            self.drop_server(pending.node);
+            self.retry(pending.attempt);
+        }
+
+        self.dispatch(now)
+    }
+
+    pub fn handle(&mut self, message: LESReceiveMessage, now: Instant) -> Vec<LESSendMessage> {
+        match message {
+            LESReceiveMessage::Connected { .. } => Vec::new(),
+            LESReceiveMessage::Disconnected { node } => {
+                self.servers.remove(&node);
+                let ids: Vec<u64> = self.pending.iter()
+                    .filter(|&(_, pending)| pending.node == node)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in ids {
+                    let pending = self.pending.remove(&id).unwrap();
+                    self.retry(pending.attempt);
+                }
+                self.dispatch(now)
+            },
+            LESReceiveMessage::Normal { node, data } => self.handle_normal(node, data, now),
+        }
+    }
+
+    fn handle_normal(&mut self, node: H512, data: LESMessage, now: Instant) -> Vec<LESSendMessage> {
+        let id = match data {
+            LESMessage::Status(ref pairs) => return self.receive_status(node, pairs, now),
+            LESMessage::Announce { head_hash, head_number, head_total_difficulty, .. } => {
+                if let Some(server) = self.servers.get_mut(&node) {
+                    server.status.head_hash = head_hash;
+                    server.status.head_number = head_number;
+                    server.status.head_total_difficulty = head_total_difficulty;
+                }
+                return Vec::new();
+            },
+            LESMessage::BlockHeaders { id, .. } | LESMessage::Receipts { id, .. } |
+            LESMessage::Proofs { id, .. } | LESMessage::ContractCodes { id, .. } => id,
+            ref msg => {
+                trace!("ignoring LES message {:?} from {}", msg, node);
+                return Vec::new();
+            },
+        };
+
+        let pending = match self.pending.remove(&id) {
+            Some(pending) => if pending.node == node {
+                pending
+            } else {
+                self.pending.insert(id, pending);
+                return Vec::new();
+            },
+            None => return Vec::new(),
+        };
+        if let Some(server) = self.servers.get_mut(&node) {
+            server.busy = false;
+        }
+
+        let mut ret = Vec::new();
+        match step(pending.stage, data) {
+            Some(Step::Done(response)) => pending.attempt.reply(Ok(response)),
+            Some(Step::Next(stage)) => ret.push(self.send(node, pending.attempt, stage, now)),
+            None => {
+                debug!("invalid LES response {} from {}", id, node);
+                self.drop_server(node);
+                self.retry(pending.attempt);
+            },
+        }
+        ret.extend(self.dispatch(now));
+        ret
+    }
+
+    fn receive_status(&mut self, node: H512, pairs: &[(String, Vec<u8>)], now: Instant) -> Vec<LESSendMessage> {
+        let status = match Status::from_pairs(pairs) {
+            Ok(status) => status,
+            Err(err) => {
+                debug!("invalid LES status from {}: {:?}", node, err);
+                self.dropped.push(node);
+                return Vec::new();
+            },
+        };
+        if status.network_id != self.network_id || status.genesis_hash != self.genesis_hash {
+            debug!("LES peer {} is on network {} with genesis {}", node, status.network_id, status.genesis_hash);
+            self.dropped.push(node);
+            return Vec::new();
+        }
+        if !status.serves_headers || !status.serves_state {
+            return Vec::new();
+        }
+
+        debug!("LES server {} at block {}", node, status.head_number);
+        self.servers.insert(node, Server { status, busy: false });
+        self.dispatch(now)
+    }
+
+    /// Send queued queries to idle servers, or fail them all if there
+    /// is no server to wait for.
+    fn dispatch(&mut self, now: Instant) -> Vec<LESSendMessage> {
+        if self.servers.is_empty() {
+            while let Some(attempt) = self.queue.pop_front() {
+                attempt.reply(Err(LightError::NoServers));
+            }
+            return Vec::new();
+        }
+
+        let mut ret = Vec::new();
+        loop {
+            let node = match self.servers.iter().find(|&(_, server)| !server.busy) {
+                Some((node, _)) => *node,
+                None => break,
+            };
+            let attempt = match self.queue.pop_front() {
+                Some(attempt) => attempt,
+                None => break,
+            };
+
+            match self.stage(&attempt.request) {
+                Ok(stage) => ret.push(self.send(node, attempt, stage, now)),
+                Err(err) => attempt.reply(Err(err)),
+            }
+        }
+        ret
+    }
+
+    fn stage(&self, request: &LightRequest) -> Result<Stage, LightError> {
+        let header = |block: H256| self.chain.header(block).ok_or(LightError::UnknownBlock(block));
+
+        Ok(match request {
+            &LightRequest::Header(hash) => Stage::Header(hash),
+            &LightRequest::Account { block, address } =>
+                Stage::Proof { header: header(block)?, address, index: None, code: false },
+            &LightRequest::Storage { block, address, index } =>
+                Stage::Proof { header: header(block)?, address, index: Some(index), code: false },
+            &LightRequest::Code { block, address } =>
+                Stage::Proof { header: header(block)?, address, index: None, code: true },
+            &LightRequest::Receipts(block) => Stage::Receipts(header(block)?),
+        })
+    }
+
+    fn send(&mut self, node: H512, attempt: Attempt, stage: Stage, now: Instant) -> LESSendMessage {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let data = match stage {
+            Stage::Header(hash) => LESMessage::GetBlockHeaders {
+                id, origin: HeaderOrigin::Hash(hash), max_headers: 1, skip: 0, reverse: false,
+            },
+            Stage::Proof { ref header, address, index, .. } => {
+                let block = header.header_hash();
+                let mut requests = vec![ProofRequest { block, account_key: None, key: keccak(address.as_ref()) }];
+                if let Some(index) = index {
+                    requests.push(ProofRequest { block, account_key: Some(keccak(address.as_ref())), key: keccak(index.as_ref()) });
+                }
+                LESMessage::GetProofs { id, requests }
+            },
+            Stage::Code { block, address, .. } =>
+                LESMessage::GetContractCodes { id, requests: vec![(block, keccak(address.as_ref()))] },
+            Stage::Receipts(ref header) =>
+                LESMessage::GetReceipts { id, hashes: vec![header.header_hash()] },
+        };
+
+        if let Some(server) = self.servers.get_mut(&node) {
+            server.busy = true;
+        }
+        self.pending.insert(id, Pending { node, attempt, stage, sent: now });
+        LESSendMessage { node: RLPxNode::Peer(node), data }
+    }
+
+    fn retry(&mut self, mut attempt: Attempt) {
+        attempt.attempts += 1;
+        if attempt.attempts >= MAX_ATTEMPTS {
+            attempt.reply(Err(LightError::Failed));
+        } else {
+            self.queue.push_front(attempt);
+        }
+    }
+
+    fn drop_server(&mut self, node: H512) {
+        self.servers.remove(&node);
+        self.dropped.push(node);
+    }
+}
+
+/// Verify a response against the stage it answers. Returns `None` if
+/// it is invalid.
+fn step(stage: Stage, data: LESMessage) -> Option<Step> {
+    match (stage, data) {
+        (Stage::Header(hash), LESMessage::BlockHeaders { headers, .. }) => {
+            headers.into_iter().next()
+                .and_then(|header| if header.header_hash() == hash { Some(header) } else { None })
+                .map(|header| Step::Done(LightResponse::Header(header)))
+        },
+        (Stage::Proof { header, address, index, code }, LESMessage::Proofs { proofs, .. }) => {
+            let account = verify_account(header.state_root, address, proofs.get(0)?).ok()?;
+            if let Some(index) = index {
+                let value = match account {
+                    Some(account) => verify_storage(account.storage_root, index, proofs.get(1)?).ok()?,
+                    None => M256::zero(),
+                };
+                return Some(Step::Done(LightResponse::Storage(value)));
+            }
+            if !code {
+                return Some(Step::Done(LightResponse::Account(account)));
+            }
+            match account {
+                Some(ref account) if account.code_hash != keccak(&[]) => Some(Step::Next(Stage::Code {
+                    block: header.header_hash(), address, code_hash: account.code_hash,
+                })),
+                _ => Some(Step::Done(LightResponse::Code(Vec::new()))),
+            }
+        },
+        (Stage::Code { code_hash, .. }, LESMessage::ContractCodes { codes, .. }) => {
+            codes.into_iter().next()
+                .and_then(|code| if keccak(&code) == code_hash { Some(code) } else { None })
+                .map(|code| Step::Done(LightResponse::Code(code)))
+        },
+        (Stage::Receipts(header), LESMessage::Receipts { receipts, .. }) => {
+            receipts.into_iter().next()
+                .and_then(|receipts| if receipts_root(&receipts) == header.receipts_root { Some(receipts) } else { None })
+                .map(|receipts| Step::Done(LightResponse::Receipts(receipts)))
+        },
+        _ => None,
+    }
+}
+
+/// Run a LES client on its own thread, returning the sender queries
+/// are submitted through.
+pub fn start(
+    addr: SocketAddr, public_addr: IpAddr, secret_key: SecretKey, client_version: String,
+    network_id: usize, chain: ChainDatabase, bootstrap_nodes: Vec<DPTNode>, config: DevP2PConfig,
+) -> mpsc::UnboundedSender<LightQuery> {
+    let (sender, queries) = mpsc::unbounded();
+    thread::spawn(move || {
+        run(addr, public_addr, secret_key, client_version, network_id, chain, bootstrap_nodes, config, queries)
+    });
+    sender
+}
+
+fn head(chain: &ChainDatabase) -> (Header, U256) {
+    chain.fetch(chain.best_hash().unwrap()).unwrap()
+}
+
+fn run(
+    addr: SocketAddr, public_addr: IpAddr, secret_key: SecretKey, client_version: String,
+    network_id: usize, chain: ChainDatabase, bootstrap_nodes: Vec<DPTNode>, config: DevP2PConfig,
+    mut queries: mpsc::UnboundedReceiver<LightQuery>,
+) {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let genesis_hash = chain.canonical_hash(U256::zero()).unwrap();
+    let (header, total_difficulty) = head(&chain);
+    let status = Status {
+        protocol_version: proto::PROTOCOL_VERSION,
+        network_id,
+        head_total_difficulty: total_difficulty,
+        head_hash: header.header_hash(),
+        head_number: header.number,
+        genesis_hash,
+        serves_headers: false,
+        serves_state: false,
+    };
+    let mut stream = LESStream::new(&addr, &public_addr, &handle, secret_key, client_version,
+                                    status, bootstrap_nodes, config).unwrap();
+    let mut client = LightClient::new(chain.clone(), network_id, genesis_hash);
+    let mut next_tick = Instant::now() + Duration::new(TICK_INTERVAL, 0);
+
+    loop {
+        let messages = {
+            let timeout = Timeout::new_at(next_tick, &handle).unwrap();
+            let next = (&mut stream).into_future().select2((&mut queries).into_future());
+            let ret = match core.run(next.select2(timeout)) {
+                Ok(ret) => ret,
+                Err(_) => break,
+            };
+
+            match ret {
+                future::Either::A((future::Either::A(((message, _), _)), _)) => {
+                    match message {
+                        Some(message) => client.handle(message, Instant::now()),
+                        None => break,
+                    }
+                },
+                future::Either::A((future::Either::B(((query, _), _)), _)) => {
+                    match query {
+                        Some(query) => client.query(query, Instant::now()),
+                        None => break,
+                    }
+                },
+                future::Either::B(_) => {
+                    next_tick = Instant::now() + Duration::new(TICK_INTERVAL, 0);
+                    client.tick(Instant::now())
+                },
+            }
+        };
+
+        for message in messages {
+            core.run((&mut stream).send(message)).unwrap();
+        }
+        for node in client.take_disconnects() {
+            stream.disconnect_peer(node);
+        }
+
+        let (header, total_difficulty) = head(&chain);
+        stream.set_head(header.header_hash(), header.number, total_difficulty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::MemoryKeyValueStore;
+    use spec::ChainSpec;
+    use validator::EthereumProcessor;
+    use rlp;
+    use std::sync::Arc;
+
+    #[test]
+    fn verified_account_query() {
+        let address = Address::from(1u64);
+        let mut spec = ChainSpec::classic();
+        spec.genesis.alloc = vec![(address, U256::from(100u64))];
+        let processor = EthereumProcessor::new(Arc::new(MemoryKeyValueStore::new()), spec.clone());
+        let genesis = processor.best_header();
+        let mut client = LightClient::new(processor.chain().clone(), spec.network_id, genesis.header_hash());
+        let node = H512::from(1u64);
+        let now = Instant::now();
+
+        let request = LightRequest::Account { block: genesis.header_hash(), address };
+        let (reply, response) = oneshot::channel();
+        assert_eq!(client.query(LightQuery { request: request.clone(), reply }, now), Vec::new());
+        assert_eq!(response.wait().unwrap(), Err(LightError::NoServers));
+
+        let mut pairs = match (Status {
+            protocol_version: proto::PROTOCOL_VERSION,
+            network_id: spec.network_id,
+            head_total_difficulty: genesis.difficulty,
+            head_hash: genesis.header_hash(),
+            head_number: genesis.number,
+            genesis_hash: genesis.header_hash(),
+            serves_headers: true,
+            serves_state: true,
+        }).to_message() {
+            LESMessage::Status(pairs) => pairs,
+            _ => unreachable!(),
+        };
+        pairs.push(("serveHeaders".to_string(), vec![0xc0]));
+        pairs.push(("serveStateSince".to_string(), rlp::encode(&0u64).to_vec()));
+
+        client.handle(LESReceiveMessage::Connected { node }, now);
+        assert_eq!(client.handle(LESReceiveMessage::Normal { node, data: LESMessage::Status(pairs) }, now),
+                   Vec::new());
+        let (reply, response) = oneshot::channel();
+        let requests = client.query(LightQuery { request: request.clone(), reply }, now);
+        assert_eq!(requests, vec![LESSendMessage {
+            node: RLPxNode::Peer(node),
+            data: LESMessage::GetProofs { id: 0, requests: vec![ProofRequest {
+                block: genesis.header_hash(), account_key: None, key: keccak(address.as_ref()),
+            }] },
+        }]);
+
+        let root = processor.state().node(genesis.state_root).unwrap();
+        client.handle(LESReceiveMessage::Normal { node, data: LESMessage::Proofs {
+            id: 0, buffer: 0, proofs: vec![vec![root]],
+        } }, now);
+        let account = response.wait().unwrap().unwrap();
+        assert_eq!(account, LightResponse::Account(Some(Account {
+            nonce: U256::zero(),
+            balance: U256::from(100u64),
+            storage_root: keccak(&[0x80]),
+            code_hash: keccak(&[]),
+        })));
+
+        // The server is busy, so the next query waits until it times out.
+        let (reply, _busy) = oneshot::channel();
+        assert_eq!(client.query(LightQuery { request: request.clone(), reply }, now).len(), 1);
+        let (reply, response) = oneshot::channel();
+        assert_eq!(client.query(LightQuery { request, reply }, now), Vec::new());
+        client.tick(now + Duration::new(QUERY_TIMEOUT, 0));
+        assert_eq!(response.wait().unwrap(), Err(LightError::TimedOut));
+    }
+}
@@ -0,0 +1,349 @@
+use bigint::{H256, U256};
+use block::{Header, Receipt};
+use rlp::{self, Encodable, RlpStream, DecoderError, UntrustedRlp};
+
+/// LES version spoken. Version 1 is the one that carries the
+/// `GetProofs` and `GetContractCodes` messages used here.
+pub const PROTOCOL_VERSION: usize = 1;
+/// Number of message ids reserved by LES/1.
+pub const MESSAGE_COUNT: usize = 15;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderOrigin {
+    Hash(H256),
+    Number(U256),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A Merkle proof request. Keys are already hashed: `key` is the
+/// hashed address for account proofs, and the hashed storage index
+/// of the account with hashed address `account_key` for storage
+/// proofs.
+pub struct ProofRequest {
+    pub block: H256,
+    pub account_key: Option<H256>,
+    pub key: H256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// LES/1 messages used by the light client. Responses carry the
+/// request id and the server's flow control buffer value.
+pub enum LESMessage {
+    /// Key-value pairs, with values kept RLP encoded.
+    Status(Vec<(String, Vec<u8>)>),
+    Announce {
+        head_hash: H256,
+        head_number: U256,
+        head_total_difficulty: U256,
+        reorg_depth: U256,
+    },
+    GetBlockHeaders {
+        id: u64,
+        origin: HeaderOrigin,
+        max_headers: usize,
+        skip: usize,
+        reverse: bool,
+    },
+    BlockHeaders { id: u64, buffer: u64, headers: Vec<Header> },
+    GetReceipts { id: u64, hashes: Vec<H256> },
+    Receipts { id: u64, buffer: u64, receipts: Vec<Vec<Receipt>> },
+    GetProofs { id: u64, requests: Vec<ProofRequest> },
+    /// Trie nodes proving each requested key.
+    Proofs { id: u64, buffer: u64, proofs: Vec<Vec<Vec<u8>>> },
+    /// Pairs of block hash and hashed account address.
+    GetContractCodes { id: u64, requests: Vec<(H256, H256)> },
+    ContractCodes { id: u64, buffer: u64, codes: Vec<Vec<u8>> },
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The handshake fields of a LES status message.
+pub struct Status {
+    pub protocol_version: usize,
+    pub network_id: usize,
+    pub head_total_difficulty: U256,
+    pub head_hash: H256,
+    pub head_number: U256,
+    pub genesis_hash: H256,
+    /// Whether the peer serves headers and state, as announced by
+    /// servers only.
+    pub serves_headers: bool,
+    pub serves_state: bool,
+}
+
+impl Status {
+    pub fn from_pairs(pairs: &[(String, Vec<u8>)]) -> Result<Self, DecoderError> {
+        fn value<T: rlp::Decodable>(pairs: &[(String, Vec<u8>)], key: &str) -> Result<T, DecoderError> {
+            match pairs.iter().find(|&&(ref k, _)| k == key) {
+                Some(&(_, ref value)) => UntrustedRlp::new(value).as_val(),
+                None => Err(DecoderError::Custom("missing status key")),
+            }
+        }
+        let has = |key: &str| pairs.iter().any(|&(ref k, _)| k == key);
+
+        Ok(Status {
+            protocol_version: value(pairs, "protocolVersion")?,
+            network_id: value(pairs, "networkId")?,
+            head_total_difficulty: value(pairs, "headTd")?,
+            head_hash: value(pairs, "headHash")?,
+            head_number: value(pairs, "headNum")?,
+            genesis_hash: value(pairs, "genesisHash")?,
+            serves_headers: has("serveHeaders"),
+            serves_state: has("serveStateSince"),
+        })
+    }
+
+    /// The status message of a client, which serves nothing.
+    pub fn to_message(&self) -> LESMessage {
+        LESMessage::Status(vec![
+            ("protocolVersion".to_string(), rlp::encode(&self.protocol_version).to_vec()),
+            ("networkId".to_string(), rlp::encode(&self.network_id).to_vec()),
+            ("headTd".to_string(), rlp::encode(&self.head_total_difficulty).to_vec()),
+            ("headHash".to_string(), rlp::encode(&self.head_hash).to_vec()),
+            ("headNum".to_string(), rlp::encode(&self.head_number).to_vec()),
+            ("genesisHash".to_string(), rlp::encode(&self.genesis_hash).to_vec()),
+        ])
+    }
+}
+
+impl LESMessage {
+    pub fn id(&self) -> usize {
+        match self {
+            &LESMessage::Status(_) => 0x00,
+            &LESMessage::Announce { .. } => 0x01,
+            &LESMessage::GetBlockHeaders { .. } => 0x02,
+            &LESMessage::BlockHeaders { .. } => 0x03,
+            &LESMessage::GetReceipts { .. } => 0x06,
+            &LESMessage::Receipts { .. } => 0x07,
+            &LESMessage::GetProofs { .. } => 0x08,
+            &LESMessage::Proofs { .. } => 0x09,
+            &LESMessage::GetContractCodes { .. } => 0x0a,
+            &LESMessage::ContractCodes { .. } => 0x0b,
+            &LESMessage::Unknown => 127,
+        }
+    }
+
+    /// Decode a message with the given id. Messages the client does
+    /// not use decode as `Unknown`.
+    pub fn decode(rlp: &UntrustedRlp, id: usize) -> Result<Self, DecoderError> {
+        Ok(match id {
+            0x00 => {
+                let mut pairs = Vec::new();
+                for pair in rlp.iter() {
+                    pairs.push((pair.val_at(0)?, pair.at(1)?.as_raw().to_vec()));
+                }
+                LESMessage::Status(pairs)
+            },
+            0x01 => LESMessage::Announce {
+                head_hash: rlp.val_at(0)?,
+                head_number: rlp.val_at(1)?,
+                head_total_difficulty: rlp.val_at(2)?,
+                reorg_depth: rlp.val_at(3)?,
+            },
+            0x02 => {
+                let query = rlp.at(1)?;
+                let reverse: u32 = query.val_at(3)?;
+                LESMessage::GetBlockHeaders {
+                    id: rlp.val_at(0)?,
+                    origin: if query.at(0)?.size() == 32 {
+                        HeaderOrigin::Hash(query.val_at(0)?)
+                    } else {
+                        HeaderOrigin::Number(query.val_at(0)?)
+                    },
+                    max_headers: query.val_at(1)?,
+                    skip: query.val_at(2)?,
+                    reverse: reverse != 0,
+                }
+            },
+            0x03 => LESMessage::BlockHeaders {
+                id: rlp.val_at(0)?,
+                buffer: rlp.val_at(1)?,
+                headers: rlp.list_at(2)?,
+            },
+            0x06 => LESMessage::GetReceipts {
+                id: rlp.val_at(0)?,
+                hashes: rlp.list_at(1)?,
+            },
+            0x07 => {
+                let mut receipts = Vec::new();
+                for block in rlp.at(2)?.iter() {
+                    receipts.push(block.as_list()?);
+                }
+                LESMessage::Receipts { id: rlp.val_at(0)?, buffer: rlp.val_at(1)?, receipts }
+            },
+            0x08 => {
+                let mut requests = Vec::new();
+                for request in rlp.at(1)?.iter() {
+                    let account_key = request.at(1)?;
+                    requests.push(ProofRequest {
+                        block: request.val_at(0)?,
+                        account_key: if account_key.is_empty() { None } else { Some(account_key.as_val()?) },
+                        key: request.val_at(2)?,
+                    });
+                }
+                LESMessage::GetProofs { id: rlp.val_at(0)?, requests }
+            },
+            0x09 => {
+                let mut proofs = Vec::new();
+                for proof in rlp.at(2)?.iter() {
+                    proofs.push(proof.iter().map(|node| node.as_raw().to_vec()).collect());
+                }
+                LESMessage::Proofs { id: rlp.val_at(0)?, buffer: rlp.val_at(1)?, proofs }
+            },
+            0x0a => {
+                let mut requests = Vec::new();
+                for request in rlp.at(1)?.iter() {
+                    requests.push((request.val_at(0)?, request.val_at(1)?));
+                }
+                LESMessage::GetContractCodes { id: rlp.val_at(0)?, requests }
+            },
+            0x0b => LESMessage::ContractCodes {
+                id: rlp.val_at(0)?,
+                buffer: rlp.val_at(1)?,
+                codes: rlp.list_at(2)?,
+            },
+            _ => LESMessage::Unknown,
+        })
+    }
+}
+
+impl Encodable for LESMessage {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            &LESMessage::Status(ref pairs) => {
+                s.begin_list(pairs.len());
+                for &(ref key, ref value) in pairs {
+                    s.begin_list(2);
+                    s.append(key);
+                    s.append_raw(value, 1);
+                }
+            },
+            &LESMessage::Announce { head_hash, head_number, head_total_difficulty, reorg_depth } => {
+                s.begin_list(5);
+                s.append(&head_hash);
+                s.append(&head_number);
+                s.append(&head_total_difficulty);
+                s.append(&reorg_depth);
+                s.begin_list(0);
+            },
+            &LESMessage::GetBlockHeaders { id, ref origin, max_headers, skip, reverse } => {
+                s.begin_list(2);
+                s.append(&id);
+                s.begin_list(4);
+                match origin {
+                    &HeaderOrigin::Hash(ref hash) => s.append(hash),
+                    &HeaderOrigin::Number(ref number) => s.append(number),
+                };
+                s.append(&max_headers);
+                s.append(&skip);
+                s.append(&if reverse { 1u32 } else { 0u32 });
+            },
+            &LESMessage::BlockHeaders { id, buffer, ref headers } => {
+                s.begin_list(3);
+                s.append(&id);
+                s.append(&buffer);
+                s.append_list(headers);
+            },
+            &LESMessage::GetReceipts { id, ref hashes } => {
+                s.begin_list(2);
+                s.append(&id);
+                s.append_list(hashes);
+            },
+            &LESMessage::Receipts { id, buffer, ref receipts } => {
+                s.begin_list(3);
+                s.append(&id);
+                s.append(&buffer);
+                s.begin_list(receipts.len());
+                for block in receipts {
+                    s.append_list(block);
+                }
+            },
+            &LESMessage::GetProofs { id, ref requests } => {
+                s.begin_list(2);
+                s.append(&id);
+                s.begin_list(requests.len());
+                for request in requests {
+                    s.begin_list(4);
+                    s.append(&request.block);
+                    match request.account_key {
+                        Some(ref key) => s.append(key),
+                        None => s.append_empty_data(),
+                    };
+                    s.append(&request.key);
+                    s.append(&0u32);
+                }
+            },
+            &LESMessage::Proofs { id, buffer, ref proofs } => {
+                s.begin_list(3);
+                s.append(&id);
+                s.append(&buffer);
+                s.begin_list(proofs.len());
+                for proof in proofs {
+                    s.begin_list(proof.len());
+                    for node in proof {
+                        s.append_raw(node, 1);
+                    }
+                }
+            },
+            &LESMessage::GetContractCodes { id, ref requests } => {
+                s.begin_list(2);
+                s.append(&id);
+                s.begin_list(requests.len());
+                for &(ref block, ref account_key) in requests {
+                    s.begin_list(2);
+                    s.append(block);
+                    s.append(account_key);
+                }
+            },
+            &LESMessage::ContractCodes { id, buffer, ref codes } => {
+                s.begin_list(3);
+                s.append(&id);
+                s.append(&buffer);
+                s.append_list(codes);
+            },
+            &LESMessage::Unknown => {
+                s.begin_list(0);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let status = Status {
+            protocol_version: PROTOCOL_VERSION,
+            network_id: 1,
+            head_total_difficulty: U256::from(1000),
+            head_hash: H256::from(1u64),
+            head_number: U256::from(10),
+            genesis_hash: H256::from(2u64),
+            serves_headers: false,
+            serves_state: false,
+        };
+        let message = status.to_message();
+        let decoded = LESMessage::decode(&UntrustedRlp::new(&rlp::encode(&message)), message.id()).unwrap();
+        match decoded {
+            LESMessage::Status(ref pairs) => assert_eq!(Status::from_pairs(pairs), Ok(status)),
+            _ => panic!("expected status"),
+        }
+
+        let messages = vec![
+            LESMessage::GetBlockHeaders {
+                id: 1, origin: HeaderOrigin::Hash(H256::from(3u64)), max_headers: 1, skip: 0, reverse: false,
+            },
+            LESMessage::GetProofs { id: 2, requests: vec![
+                ProofRequest { block: H256::from(4u64), account_key: None, key: H256::from(5u64) },
+                ProofRequest { block: H256::from(4u64), account_key: Some(H256::from(5u64)), key: H256::from(6u64) },
+            ] },
+            LESMessage::Proofs { id: 2, buffer: 100, proofs: vec![vec![vec![0xc2, 0x01, 0x02]], Vec::new()] },
+            LESMessage::ContractCodes { id: 3, buffer: 100, codes: vec![vec![0x60, 0x00]] },
+        ];
+        for message in messages {
+            assert_eq!(LESMessage::decode(&UntrustedRlp::new(&rlp::encode(&message)), message.id()), Ok(message));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use bigint::{H256, H512, U256};
+use devp2p::DevP2PConfig;
+use devp2p::dpt::DPTNode;
+use devp2p::rlpx::{RLPxNode, RLPxSendMessage, RLPxReceiveMessage, CapabilityInfo};
+use futures::{Stream, Sink, Poll, Async, StartSend, AsyncSink};
+use rlp::{self, UntrustedRlp};
+use secp256k1::key::SecretKey;
+use tokio_core::reactor::Handle;
+use p2p::P2PStream;
+
+use super::proto::{LESMessage, Status, PROTOCOL_VERSION, MESSAGE_COUNT};
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LESReceiveMessage {
+    Connected { node: H512 },
+    Disconnected { node: H512 },
+    Normal { node: H512, data: LESMessage },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LESSendMessage {
+    pub node: RLPxNode,
+    pub data: LESMessage,
+}
+
+/// A LES stream over `P2PStream`, the counterpart of
+/// `p2p::ETHStream`. Our status is sent to every peer as soon as it
+/// connects.
+pub struct LESStream {
+    stream: P2PStream,
+    status: Status,
+}
+
+impl LESStream {
+    pub fn new(addr: &SocketAddr, public_addr: &IpAddr, handle: &Handle, secret_key: SecretKey,
+               client_version: String, status: Status, bootstrap_nodes: Vec<DPTNode>,
+               config: DevP2PConfig) -> Result<Self, io::Error> {
+        Ok(LESStream {
+            stream: P2PStream::new(addr, public_addr, handle, secret_key, 4, client_version,
+                                   vec![CapabilityInfo {
+                                       name: "les", version: PROTOCOL_VERSION, length: MESSAGE_COUNT,
+                                   }],
+                                   bootstrap_nodes, config)?,
+            status,
+        })
+    }
+
+    /// Set the head announced in the status sent to new peers.
+    pub fn set_head(&mut self, hash: H256, number: U256, total_difficulty: U256) {
+        self.status.head_hash = hash;
+        self.status.head_number = number;
+        self.status.head_total_difficulty = total_difficulty;
+    }
+
+    pub fn disconnect_peer(&mut self, node: H512) {
+        self.stream.disconnect_peer(node);
+    }
+}
+
+impl Stream for LESStream {
+    type Item = LESReceiveMessage;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let message = match try_ready!(self.stream.poll()) {
+                Some(message) => message,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            match message {
+                RLPxReceiveMessage::Connected { node, capabilities } => {
+                    if capabilities.len() == 0 {
+                        continue;
+                    }
+                    let status = self.status.to_message();
+                    self.start_send(LESSendMessage { node: RLPxNode::Peer(node), data: status })?;
+                    self.poll_complete()?;
+                    return Ok(Async::Ready(Some(LESReceiveMessage::Connected { node })));
+                },
+                RLPxReceiveMessage::Disconnected { node } => {
+                    return Ok(Async::Ready(Some(LESReceiveMessage::Disconnected { node })));
+                },
+                RLPxReceiveMessage::Normal { node, id, data, .. } => {
+                    match LESMessage::decode(&UntrustedRlp::new(&data), id) {
+                        Ok(data) => return Ok(Async::Ready(Some(LESReceiveMessage::Normal { node, data }))),
+                        Err(_) => debug!("undecodable LES message {} from {}", id, node),
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Sink for LESStream {
+    type SinkItem = LESSendMessage;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, val: LESSendMessage) -> StartSend<Self::SinkItem, Self::SinkError> {
+        match self.stream.start_send(RLPxSendMessage {
+            node: val.node,
+            capability_name: "les",
+            id: val.data.id(),
+            data: rlp::encode(&val.data).to_vec(),
+        })? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(_) => Ok(AsyncSink::NotReady(val)),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.stream.poll_complete()
+    }
+}
@@ -15,314 +15,339 @@ extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
 extern crate ethash;
+extern crate rocksdb;
+extern crate jsonrpc_core;
+extern crate jsonrpc_http_server;
 
 #[macro_use]
 extern crate futures;
 extern crate tokio_io;
 extern crate tokio_core;
 extern crate env_logger;
+#[macro_use]
+extern crate log;
 extern crate url;
 extern crate sha3;
 
 mod validator;
 mod patch;
-
-use validator::EthereumProcessor;
+mod db;
+mod spec;
+mod rpc;
+mod filter;
+mod serve;
+mod sync;
+mod proof;
+mod txpool;
+mod les;
+mod p2p;
+mod config;
+mod cli;
+#[cfg(test)]
+mod testing;
+
+use validator::{EthereumProcessor, ImportResult};
+use bigint::U256;
+use block::Block;
+use blockchain::chain::HeaderHash;
+use db::{KeyValueStore, RocksKeyValueStore, NodeDatabase};
+use spec::ChainSpec;
+use rpc::EthereumRPC;
+use sync::{Syncer, SyncMode};
+use config::Config;
+use cli::{Command, DbCommand};
 use tokio_core::reactor::{Core, Timeout};
-use secp256k1::SECP256K1;
-use secp256k1::key::{PublicKey, SecretKey};
-use rand::os::OsRng;
+use rlp::UntrustedRlp;
 use futures::future;
 use futures::{Stream, Sink, Future};
-use std::str::FromStr;
+use futures::sync::mpsc;
 use std::time::{Instant, Duration};
-use std::collections::HashMap;
-use devp2p::{ETHSendMessage, ETHReceiveMessage, ETHMessage, ETHStream, DevP2PConfig};
-use devp2p::rlpx::RLPxNode;
+use std::sync::Arc;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write, BufWriter};
+use std::path::Path;
+use std::process;
+use p2p::ETHStream;
 use devp2p::dpt::DPTNode;
-use bigint::{H256, U256, H512};
 use url::Url;
-use sha3::{Digest, Keccak256};
-use block::{Header, Block, Transaction, transactions_root, ommers_hash, receipts_root};
-use hexutil::*;
-use blockchain::chain::HeaderHash;
 
-const GENESIS_HASH: &str = "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3";
-const GENESIS_DIFFICULTY: usize = 17179869184;
-const NETWORK_ID: usize = 1;
-
-const ETC_DAO_BLOCK: &str = "f903cff9020fa0a218e2c611f21232d857e3c8cecdcdf1f65f25a4477f98f6f47e4063807f2308a01dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d493479461c808d82a3ac53231750dadc13c777b59310bd9a0614d7d358b03cbdaf0343529673be20ad45809d02487f023e047efdce9da8affa0d33068a7f21bff5018a00ca08a3566a06be4196dfe9e39f96e431565a619d455a07bda9aa65977800376129148cbfe89d35a016dd51c95d6e6dc1e76307d315468b90100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000008638c3bf2616aa831d4c008347e7c08301482084578f7aa78fe4b883e5bda9e7a59ee4bb99e9b1bca0c52daa7054babe515b17ee98540c0889cf5e1595c5dd77496997ca84a68c8da18805276a600980199df901b9f86c018504a817c8008252089453d284357ec70ce289d6d64134dfac8e511c8a3d888b6cfa3afc058000801ba08d94a55c7ac7adbfa2285ef7f4b0c955ae1a02647452cd4ead03ee6f449675c6a067149821b74208176d78fc4dffbe37c8b64eecfd47532406b9727c4ae8eb7c9af86d018504a817c8008252089453d284357ec70ce289d6d64134dfac8e511c8a3d890116db7272d6d94000801ca06d31e3d59bfea97a34103d8ce767a8fe7a79b8e2f30af1e918df53f9e78e69aba0098e5b80e1cc436421aa54eb17e96b08fe80d28a2fbd46451b56f2bca7a321e7f86c018504a817c8008252089453d284357ec70ce289d6d64134dfac8e511c8a3d8814da2c24e0d37014801ba0fdbbc462a8a60ac3d8b13ee236b45af9b7991cf4f0f556d3af46aa5aeca242aba05de5dc03fdcb6cf6d14609dbe6f5ba4300b8ff917c7d190325d9ea2144a7a2fbf86c018504a817c8008252089453d284357ec70ce289d6d64134dfac8e511c8a3d880e301365046d5000801ba0bafb9f71cef873b9e0395b9ed89aac4f2a752e2a4b88ba3c9b6c1fea254eae73a01cef688f6718932f7705d9c1f0dd5a8aad9ddb196b826775f6e5703fdb997706c0";
-
-const BOOTSTRAP_NODES: [&str; 8] = [
-    "enode://e809c4a2fec7daed400e5e28564e23693b23b2cc5a019b612505631bbe7b9ccf709c1796d2a3d29ef2b045f210caf51e3c4f5b6d3587d43ad5d6397526fa6179@174.112.32.157:30303",
-    "enode://6e538e7c1280f0a31ff08b382db5302480f775480b8e68f8febca0ceff81e4b19153c6f8bf60313b93bef2cc34d34e1df41317de0ce613a201d1660a788a03e2@52.206.67.235:30303",
-    "enode://5fbfb426fbb46f8b8c1bd3dd140f5b511da558cd37d60844b525909ab82e13a25ee722293c829e52cb65c2305b1637fa9a2ea4d6634a224d5f400bfe244ac0de@162.243.55.45:30303",
-    "enode://42d8f29d1db5f4b2947cd5c3d76c6d0d3697e6b9b3430c3d41e46b4bb77655433aeedc25d4b4ea9d8214b6a43008ba67199374a9b53633301bca0cd20c6928ab@104.155.176.151:30303",
-    "enode://814920f1ec9510aa9ea1c8f79d8b6e6a462045f09caa2ae4055b0f34f7416fca6facd3dd45f1cf1673c0209e0503f02776b8ff94020e98b6679a0dc561b4eba0@104.154.136.117:30303",
-    "enode://72e445f4e89c0f476d404bc40478b0df83a5b500d2d2e850e08eb1af0cd464ab86db6160d0fde64bd77d5f0d33507ae19035671b3c74fec126d6e28787669740@104.198.71.200:30303",
-    "enode://39abab9d2a41f53298c0c9dc6bbca57b0840c3ba9dccf42aa27316addc1b7e56ade32a0a9f7f52d6c5db4fe74d8824bcedfeaecf1a4e533cacb71cf8100a9442@144.76.238.49:30303",
-    "enode://f50e675a34f471af2438b921914b5f06499c7438f3146f6b8936f1faeb50b8a91d0d0c24fb05a66f05865cd58c24da3e664d0def806172ddd0d4c5bdbf37747e@144.76.238.49:30306"
-];
-
-// const BOOTSTRAP_NODES: [&str; 1] = [
-//     "enode://1a686737c260539c2a80b8defe649a356806ca43f71e1915ae00c65245b893e2eee31bc0ca41f7733d31ba7cdcd60584e3c3f89cccabba08ca5bce889f44244c@127.0.0.1:30303"
-// ];
-
-// const BOOTSTRAP_NODES: [&str; 1] = [
-//     "enode://52656243997655790c1015e4c62e1afefd2f7d6b30c4434ea0a1557523348ad8515d15d0014002bdec80daba786714aa9bc4970ce99afa9e4fd6b94c98782669@35.194.140.8:30303"
-// ];
-
-// const BOOTSTRAP_NODES: [&str; 1] = [
-//     "enode://1a686737c260539c2a80b8defe649a356806ca43f71e1915ae00c65245b893e2eee31bc0ca41f7733d31ba7cdcd60584e3c3f89cccabba08ca5bce889f44244c@127.0.0.1:60606"
-// ];
-
-// const BOOTSTRAP_NODES: [&str; 1] = [
-//     "enode://3321955ec86feb439a20a295189408ac498c5390933e269fea0db3de949d0b23b69c6bab276cdf2c8ab56d019cfa6a1548e773de761151353b4390e62ce81318@127.0.0.1:30303"
-// ];
-
-fn find_and_validate(
-    processor: &mut EthereumProcessor, validated_number: U256, headers: &[Header], bodies: &HashMap<(H256, H256), (Vec<Transaction>, Vec<Header>)>
-) -> U256 {
-    let mut validated_number = validated_number.as_usize();
-    if validated_number >= headers.len() { return U256::from(validated_number); }
-
-    for i in validated_number..headers.len() {
-        if bodies.contains_key(&(headers[validated_number].transactions_root,
-                                 headers[validated_number].ommers_hash)) {
-            let body = bodies.get(&(headers[validated_number].transactions_root,
-                                    headers[validated_number].ommers_hash)).unwrap();
-            let block = Block {
-                header: headers[validated_number].clone(),
-                transactions: body.0.clone(),
-                ommers: body.1.clone(),
-            };
-            println!("validating block {:?} ...", block);
-            assert!(processor.put(block));
-            validated_number += 1;
-        } else {
-            println!("block body not yet found: {}", validated_number);
-            return U256::from(validated_number);
-        }
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match cli::parse_args(&args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            process::exit(1);
+        },
+    };
+
+    match command {
+        Command::Help => print!("{}", cli::USAGE),
+        Command::Run(config) => run(config),
+        Command::Import { config, path } => import(config, &path),
+        Command::Export { config, path, from, to } => export(config, &path, from, to),
+        Command::Db { config, command } => db(config, command),
     }
+}
 
-    U256::from(validated_number)
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
 }
 
-fn main() {
-    env_logger::init();
+fn init_logger(config: &Config) {
+    let result = match config.log {
+        Some(ref filter) => env_logger::LogBuilder::new().parse(filter).init(),
+        None => env_logger::init(),
+    };
+    result.unwrap();
+}
 
-    let addr = "0.0.0.0:60606".parse().unwrap();
-    let public_addr = "127.0.0.1".parse().unwrap();
+/// Open a RocksDB database in the data directory, creating both if
+/// needed.
+fn open_store(config: &Config, path: &Path) -> Arc<KeyValueStore> {
+    fs::create_dir_all(&config.data_dir)
+        .unwrap_or_else(|err| fail(format!("{}: {}", config.data_dir.display(), err)));
+    Arc::new(RocksKeyValueStore::open(path)
+             .unwrap_or_else(|err| fail(format!("{}: {}", path.display(), err))))
+}
+
+fn open(config: &Config) -> EthereumProcessor {
+    let spec = match config.chain {
+        Some(ref path) => ChainSpec::from_file(path)
+            .unwrap_or_else(|err| fail(format!("{}: {}", path.display(), err))),
+        None => ChainSpec::classic(),
+    };
+    println!("using chain {}", spec.name);
+    EthereumProcessor::new(open_store(config, &config.chain_data_dir()), spec)
+}
+
+/// Parse enode URLs, exiting on an invalid one.
+fn parse_nodes(urls: &[String]) -> Vec<DPTNode> {
+    urls.iter()
+        .map(|v| Url::parse(v).map_err(|e| e.to_string())
+             .and_then(|url| DPTNode::from_url(&url).map_err(|e| format!("{:?}", e)))
+             .unwrap_or_else(|err| fail(format!("invalid enode URL {}: {}", v, err))))
+        .collect()
+}
+
+fn run(config: Config) {
+    init_logger(&config);
 
     let mut core = Core::new().unwrap();
     let handle = core.handle();
 
+    let mode = if config.light {
+        SyncMode::Light
+    } else if config.state_sync {
+        SyncMode::State
+    } else {
+        SyncMode::Full
+    };
+    let processor = open(&config);
+    let nodes = NodeDatabase::new(open_store(&config, &config.nodes_dir()));
+    let network_id = processor.spec().network_id;
+    let bootnodes = if config.bootnodes.len() > 0 { &config.bootnodes } else { &processor.spec().bootnodes };
+    let mut bootstrap_nodes = parse_nodes(bootnodes);
+    let static_nodes = parse_nodes(&config.static_nodes);
+    let trusted_nodes = parse_nodes(&config.trusted_nodes);
+    // Peers from previous sessions are contacted along with the
+    // bootnodes.
+    let known_nodes: Vec<DPTNode> = nodes.nodes().into_iter()
+        .filter(|node| !bootstrap_nodes.iter().any(|bootnode| bootnode.id == node.id))
+        .collect();
+    println!("{} known peers from previous sessions", known_nodes.len());
+    bootstrap_nodes.extend(known_nodes);
+
+    // Only the default key file is generated if missing.
+    let secret_key = p2p::node_key(&config.node_key_path(), config.node_key.is_none())
+        .unwrap_or_else(|err| fail(err));
+    println!("enode://{:x}@{}:{}", p2p::node_id(&secret_key), config.public_address, config.port);
+
+    // Light nodes hold no state, so state queries are answered
+    // through LES servers.
+    let light = match mode {
+        SyncMode::Light => Some(les::start(
+            config.les_address(), config.public_address, secret_key.clone(), config.client_id.clone(),
+            network_id, processor.chain().clone(), bootstrap_nodes.clone(), config.devp2p())),
+        _ => None,
+    };
+
+    // Both senders are kept here even without JSON-RPC, so that the
+    // channels stay open.
+    let (submitted_sender, mut submitted) = mpsc::unbounded();
+    let _rpc_server = if config.rpc {
+        let server = rpc::start(&config.rpc_address, EthereumRPC::new(
+            processor.spec().clone(), processor.chain().clone(), processor.state().clone(),
+            submitted_sender.clone(), light.clone(), config.client_id.clone()))
+            .unwrap_or_else(|err| fail(format!("JSON-RPC on {}: {}", config.rpc_address, err)));
+        println!("JSON-RPC listening on {}", config.rpc_address);
+        Some(server)
+    } else {
+        None
+    };
+
     let mut client = ETHStream::new(
-        &addr, &public_addr, &handle,
-        SecretKey::new(&SECP256K1, &mut OsRng::new().unwrap()),
-        "etclient Rust/0.1.0".to_string(), 1,
-        H256::from_str(GENESIS_HASH).unwrap(),
-        H256::from_str(GENESIS_HASH).unwrap(),
-        U256::from(GENESIS_DIFFICULTY),
-        BOOTSTRAP_NODES.iter().map(|v| DPTNode::from_url(&Url::parse(v).unwrap()).unwrap()).collect(),
-        DevP2PConfig {
-            ping_interval: Duration::new(600, 0),
-            ping_timeout_interval: Duration::new(700, 0),
-            optimal_peers_len: 25,
-            optimal_peers_interval: Duration::new(5, 0),
-            reconnect_dividend: 5,
-            listen: false,
-        }).unwrap();
-
-    let mut best_number: U256 = U256::zero();
-    let mut best_hash: H256 = H256::from_str(GENESIS_HASH).unwrap();
-    let mut validated_number: U256 = U256::zero();
-    let mut known_headers: Vec<Header> = Vec::new();
-    let mut known_bodies: HashMap<(H256, H256), (Vec<Transaction>, Vec<Header>)> = HashMap::new();
-    let mut processor: EthereumProcessor = EthereumProcessor::new();
-
-    let mut got_bodies_for_current = true;
-
-    let dur = Duration::new(10, 0);
-    let req_max_headers = 192;
-    let mut when = Instant::now() + dur;
-
-    let (mut client_sender, mut client_receiver) = client.split();
-    let mut client_future = client_receiver.into_future();
-    let mut timeout = Timeout::new(dur, &handle).unwrap().boxed();
-
-    let mut active_peers = 0;
+        &config.listen_address(), &config.public_address, &handle, secret_key,
+        config.client_id.clone(), network_id,
+        processor.genesis_hash(),
+        processor.best_hash(),
+        processor.best_total_difficulty(),
+        bootstrap_nodes,
+        config.devp2p()).unwrap();
+    for node in &static_nodes {
+        client.add_static_node(node.clone());
+    }
 
-    loop {
-        let ret = match core.run(
-            client_future
-                .select2(timeout)
-        ) {
-            Ok(ret) => ret,
-            Err(_) => break,
-        };
+    let mut syncer = Syncer::new(processor, mode, Duration::new(10, 0));
+    for node in static_nodes.iter().chain(trusted_nodes.iter()) {
+        syncer.trust(node.id);
+    }
+    println!("resuming {:?} sync from block {}", syncer.mode(), syncer.best_number());
 
-        let (val, new_client_receiver) = match ret {
-            future::Either::A(((val, new_client), t)) => {
-                timeout = t.boxed();
-                (val, new_client)
-            },
-            future::Either::B((_, fu)) => {
-                client_future = fu;
-
-                println!("request downloading headers and bodies due to timeout ...");
-                client_sender = core.run(client_sender.send(ETHSendMessage {
-                    node: RLPxNode::Any,
-                    data: ETHMessage::GetBlockHeadersByHash {
-                        hash: best_hash,
-                        max_headers: req_max_headers,
-                        skip: 0,
-                        reverse: false,
-                    }
-                })).unwrap();
+    loop {
+        let messages = {
+            let timeout = Timeout::new_at(syncer.next_tick(), &handle).unwrap();
+            let next = (&mut client).into_future().select2((&mut submitted).into_future());
+            let ret = match core.run(next.select2(timeout)) {
+                Ok(ret) => ret,
+                Err(_) => break,
+            };
 
-                let mut req_header_hashes = Vec::new();
-                for header in &known_headers {
-                    if !known_bodies.contains_key(&(header.transactions_root,
-                                                    header.ommers_hash)) {
-                        req_header_hashes.push(header.header_hash());
+            match ret {
+                future::Either::A((future::Either::A(((val, _), _)), _)) => {
+                    let val = match val {
+                        Some(val) => val,
+                        None => break,
+                    };
+                    syncer.handle(val, Instant::now())
+                },
+                future::Either::A((future::Either::B(((transaction, _), _)), _)) => {
+                    match transaction {
+                        Some(transaction) => syncer.submit(transaction),
+                        None => Vec::new(),
                     }
-                }
-                client_sender = core.run(client_sender.send(ETHSendMessage {
-                    node: RLPxNode::Any,
-                    data: ETHMessage::GetBlockBodies(req_header_hashes),
-                })).unwrap();
+                },
+                future::Either::B(_) => syncer.tick(Instant::now()),
+            }
+        };
 
-                validated_number = find_and_validate(
-                    &mut processor, validated_number, &known_headers, &known_bodies);
+        for message in messages {
+            core.run((&mut client).send(message)).unwrap();
+        }
+        for node in syncer.take_disconnects() {
+            client.disconnect_peer(node);
+        }
+        for node in syncer.take_verified() {
+            if let Some(peer) = client.peer(node) {
+                nodes.put_node(&peer);
+            }
+        }
+        // Advertise the current head in the status sent to new peers.
+        client.set_best_hash(syncer.processor().best_hash());
+        client.set_total_difficulty(syncer.processor().best_total_difficulty());
 
-                timeout = Timeout::new(dur, &handle).unwrap().boxed();
+        debug!("current active peers: {}", syncer.active_peers());
+    }
+}
 
-                continue;
-            }
+/// Import RLP-encoded blocks, as written by `export`, stopping at the
+/// first invalid one. In light mode only their headers are imported.
+fn import(config: Config, path: &Path) {
+    init_logger(&config);
+    let mut processor = open(&config);
+
+    let mut data = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut data))
+        .unwrap_or_else(|err| fail(format!("{}: {}", path.display(), err)));
+
+    let mut offset = 0;
+    let mut imported = 0;
+    while offset < data.len() {
+        let rlp = UntrustedRlp::new(&data[offset..]);
+        let block: Block = match rlp.payload_info().and_then(|info| {
+            offset += info.header_len + info.value_len;
+            rlp.as_val()
+        }) {
+            Ok(block) => block,
+            Err(err) => fail(format!("undecodable block after {} blocks: {:?}", imported, err)),
         };
 
-        if val.is_none() {
-            break;
-        }
-        let val = val.unwrap();
-
-        match val {
-            ETHReceiveMessage::Normal {
-                node, data, version
-            } => {
-                match data {
-                    ETHMessage::Status { .. } => (),
-
-                    ETHMessage::Transactions(_) => {
-                        println!("received new transactions");
-                    },
-
-                    ETHMessage::GetBlockHeadersByNumber {
-                        number, max_headers, skip, reverse
-                    } => {
-                        if number == U256::from(1920000) {
-                            println!("requested DAO header");
-                            let block_raw = read_hex(ETC_DAO_BLOCK).unwrap();
-                            let block: Block = rlp::decode(&block_raw);
-                            client_sender = core.run(client_sender.send(ETHSendMessage {
-                                node: RLPxNode::Peer(node),
-                                data: ETHMessage::BlockHeaders(vec![ block.header ]),
-                            })).unwrap();
-                        } else {
-                            println!("requested header {}", number);
-                            client_sender = core.run(client_sender.send(ETHSendMessage {
-                                node: RLPxNode::Peer(node),
-                                data: ETHMessage::BlockHeaders(Vec::new()),
-                            })).unwrap();
-                        }
-                    },
-
-                    ETHMessage::GetBlockHeadersByHash {
-                        hash, max_headers, skip, reverse
-                    } => {
-                        println!("requested header {}", hash);
-                        client_sender = core.run(client_sender.send(ETHSendMessage {
-                            node: RLPxNode::Peer(node),
-                            data: ETHMessage::BlockHeaders(Vec::new()),
-                        })).unwrap();
-                    },
-
-                    ETHMessage::GetBlockBodies(hash) => {
-                        println!("requested body {:?}", hash);
-                        client_sender = core.run(client_sender.send(ETHSendMessage {
-                            node: RLPxNode::Peer(node),
-                            data: ETHMessage::BlockBodies(Vec::new()),
-                        })).unwrap();
-                    },
-
-                    ETHMessage::BlockHeaders(ref headers) => {
-                        println!("received block headers of len {}", headers.len());
-                        if got_bodies_for_current {
-                            for header in headers {
-                                if header.parent_hash == best_hash {
-                                    best_hash = header.header_hash();
-                                    best_number = header.number;
-                                    known_headers.push(header.clone());
-                                }
-                            }
-                        }
-                        println!("new best number {}", best_number);
-
-                        println!("request downloading headers and bodies for new ...");
-                        client_sender = core.run(client_sender.send(ETHSendMessage {
-                            node: RLPxNode::Any,
-                            data: ETHMessage::GetBlockHeadersByHash {
-                                hash: best_hash,
-                                max_headers: req_max_headers,
-                                skip: 0,
-                                reverse: false,
-                            }
-                        })).unwrap();
-
-                        let mut req_header_hashes = Vec::new();
-                        for header in &known_headers {
-                            if !known_bodies.contains_key(&(header.transactions_root,
-                                                            header.ommers_hash)) {
-                                req_header_hashes.push(header.header_hash());
-                            }
-                        }
-                        client_sender = core.run(client_sender.send(ETHSendMessage {
-                            node: RLPxNode::Any,
-                            data: ETHMessage::GetBlockBodies(req_header_hashes),
-                        })).unwrap();
-
-                        validated_number = find_and_validate(
-                            &mut processor, validated_number, &known_headers, &known_bodies);
-
-                        timeout = Timeout::new(dur, &handle).unwrap().boxed();
-                    },
-
-                    ETHMessage::BlockBodies(ref bodies) => {
-                        println!("received block bodies of len {}", bodies.len());
-
-                        for body in bodies {
-                            known_bodies.insert((transactions_root(&body.0),
-                                                 ommers_hash(&body.1)),
-                                                (body.0.clone(), body.1.clone()));
-                        }
-                    },
-
-                    msg => {
-                        println!("received {:?}", msg);
-                    },
-                }
-            },
-            ETHReceiveMessage::Connected { .. } => {
-                active_peers += 1;
-            },
-            ETHReceiveMessage::Disconnected { .. } => {
-                active_peers -= 1;
-            },
+        let number = block.header.number;
+        let result = if config.light {
+            processor.put_header(block.header)
+        } else {
+            processor.put(block)
+        };
+        match result {
+            Ok(ImportResult::AlreadyKnown) => (),
+            Ok(_) => imported += 1,
+            Err(err) => fail(format!("invalid block {}: {:?}", number, err)),
         }
+    }
+
+    println!("imported {} blocks, best block is {}", imported, processor.best_header().number);
+}
+
+/// Write the canonical blocks `from` to `to` RLP-encoded, one after
+/// another.
+fn export(config: Config, path: &Path, from: u64, to: Option<u64>) {
+    init_logger(&config);
+    let processor = open(&config);
+    let chain = processor.chain();
+    let to = to.map(U256::from).unwrap_or(processor.best_header().number);
+
+    let file = File::create(path).unwrap_or_else(|err| fail(format!("{}: {}", path.display(), err)));
+    let mut writer = BufWriter::new(file);
+    let mut number = U256::from(from);
+    while number <= to {
+        let hash = chain.canonical_hash(number)
+            .unwrap_or_else(|| fail(format!("block {} is not in the canonical chain", number)));
+        let header = chain.header(hash).unwrap();
+        let (transactions, ommers) = chain.body(hash)
+            .unwrap_or_else(|| fail(format!("block {} has no body, was it synced in light mode?", number)));
+
+        writer.write_all(&rlp::encode(&Block { header, transactions, ommers }))
+            .unwrap_or_else(|err| fail(format!("{}: {}", path.display(), err)));
+        number = number + U256::one();
+    }
+    writer.flush().unwrap_or_else(|err| fail(format!("{}: {}", path.display(), err)));
 
-        println!("current active peers: {}", active_peers);
+    println!("exported blocks {} to {}", from, to);
+}
 
-        client_future = new_client_receiver.into_future();
+fn db(config: Config, command: DbCommand) {
+    match command {
+        DbCommand::Info => {
+            let processor = open(&config);
+            let chain = processor.chain();
+            let best = processor.best_header();
+            println!("genesis: {}", processor.genesis_hash());
+            println!("best block: {} ({})", best.number, best.header_hash());
+            println!("total difficulty: {}", processor.best_total_difficulty());
+            if let Some(pivot) = chain.state_pivot() {
+                println!("downloading state of block {}", pivot);
+            }
+            if let Some(next) = chain.receipt_sync_next() {
+                println!("receipts downloaded up to block {}", next);
+            }
+        },
+        DbCommand::Remove { confirmed } => {
+            let path = config.chain_data_dir();
+            if !confirmed {
+                print!("remove the chain database in {}? [y/N] ", path.display());
+                io::stdout().flush().unwrap();
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)
+                    .unwrap_or_else(|err| fail(format!("stdin: {}", err)));
+                if !["y", "yes"].contains(&answer.trim().to_lowercase().as_str()) {
+                    return;
+                }
+            }
+            fs::remove_dir_all(&path)
+                .unwrap_or_else(|err| fail(format!("{}: {}", path.display(), err)));
+            println!("removed {}", path.display());
+        },
     }
 }
@@ -0,0 +1,153 @@
+use bigint::{H256, H512, U256};
+use devp2p::DevP2PConfig;
+use devp2p::dpt::DPTNode;
+use devp2p::rlpx::{RLPxNode, RLPxSendMessage, RLPxReceiveMessage, CapabilityInfo};
+use futures::{Stream, Sink, Poll, Async, StartSend, AsyncSink};
+use rlp::{self, UntrustedRlp};
+use secp256k1::key::SecretKey;
+use tokio_core::reactor::Handle;
+
+use super::P2PStream;
+use super::proto::{ETHMessage, PROTOCOL_VERSION, MESSAGE_COUNT, ETH62_MESSAGE_COUNT};
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ETHReceiveMessage {
+    /// A peer completed the handshake, speaking eth `version`.
+    Connected {
+        node: H512,
+        version: usize,
+    },
+    Disconnected {
+        node: H512,
+    },
+    Normal {
+        node: H512,
+        version: usize,
+        data: ETHMessage,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETHSendMessage {
+    pub node: RLPxNode,
+    pub data: ETHMessage,
+}
+
+/// eth/63 and eth/62 over `P2PStream`, the counterpart of
+/// `devp2p::ETHStream`, which only speaks eth/62. rlpx picks the
+/// highest version a peer shares, and drops eth/63 messages sent to
+/// eth/62 peers, so callers check the version a peer connected with
+/// before requesting state or receipts. Our status, with our network
+/// id, is sent to every peer as soon as it connects.
+pub struct ETHStream {
+    stream: P2PStream,
+    network_id: usize,
+    genesis_hash: H256,
+    best_hash: H256,
+    total_difficulty: U256,
+}
+
+impl ETHStream {
+    pub fn new(addr: &SocketAddr, public_addr: &IpAddr, handle: &Handle, secret_key: SecretKey,
+               client_version: String, network_id: usize, genesis_hash: H256, best_hash: H256,
+               total_difficulty: U256, bootstrap_nodes: Vec<DPTNode>,
+               config: DevP2PConfig) -> Result<Self, io::Error> {
+        Ok(ETHStream {
+            stream: P2PStream::new(addr, public_addr, handle, secret_key, 4, client_version,
+                                   vec![CapabilityInfo { name: "eth", version: PROTOCOL_VERSION, length: MESSAGE_COUNT },
+                                        CapabilityInfo { name: "eth", version: 62, length: ETH62_MESSAGE_COUNT }],
+                                   bootstrap_nodes, config)?,
+            network_id, genesis_hash, best_hash, total_difficulty,
+        })
+    }
+
+    /// Address of a connected peer, if discovery knows it.
+    pub fn peer(&self, id: H512) -> Option<DPTNode> {
+        self.stream.peer(id)
+    }
+
+    /// Keep a connection to `node` open at all times.
+    pub fn add_static_node(&mut self, node: DPTNode) {
+        self.stream.add_static_node(node);
+    }
+
+    pub fn disconnect_peer(&mut self, id: H512) {
+        self.stream.disconnect_peer(id);
+    }
+
+    pub fn set_best_hash(&mut self, hash: H256) {
+        self.best_hash = hash;
+    }
+
+    pub fn set_total_difficulty(&mut self, total_difficulty: U256) {
+        self.total_difficulty = total_difficulty;
+    }
+}
+
+impl Stream for ETHStream {
+    type Item = ETHReceiveMessage;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let message = match try_ready!(self.stream.poll()) {
+                Some(message) => message,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            match message {
+                RLPxReceiveMessage::Connected { node, capabilities } => {
+                    if capabilities.len() == 0 {
+                        continue;
+                    }
+                    let version = capabilities[0].version;
+                    let status = ETHMessage::Status {
+                        protocol_version: version,
+                        network_id: self.network_id,
+                        total_difficulty: self.total_difficulty,
+                        best_hash: self.best_hash,
+                        genesis_hash: self.genesis_hash,
+                    };
+                    self.start_send(ETHSendMessage { node: RLPxNode::Peer(node), data: status })?;
+                    self.poll_complete()?;
+                    return Ok(Async::Ready(Some(ETHReceiveMessage::Connected { node, version })));
+                },
+                RLPxReceiveMessage::Disconnected { node } => {
+                    return Ok(Async::Ready(Some(ETHReceiveMessage::Disconnected { node })));
+                },
+                RLPxReceiveMessage::Normal { node, capability, id, data } => {
+                    match ETHMessage::decode(&UntrustedRlp::new(&data), id) {
+                        Ok(data) => return Ok(Async::Ready(Some(ETHReceiveMessage::Normal {
+                            node, version: capability.version, data,
+                        }))),
+                        Err(_) => debug!("undecodable eth message {} from {}", id, node),
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Sink for ETHStream {
+    type SinkItem = ETHSendMessage;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, val: ETHSendMessage) -> StartSend<Self::SinkItem, Self::SinkError> {
+        match self.stream.start_send(RLPxSendMessage {
+            node: val.node,
+            capability_name: "eth",
+            id: val.data.id(),
+            data: rlp::encode(&val.data).to_vec(),
+        })? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(_) => Ok(AsyncSink::NotReady(val)),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.stream.poll_complete()
+    }
+}
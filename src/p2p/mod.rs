@@ -0,0 +1,233 @@
+mod eth;
+mod proto;
+mod rlpx;
+
+pub use self::eth::{ETHStream, ETHSendMessage, ETHReceiveMessage};
+pub use self::proto::ETHMessage;
+
+use bigint::{H256, H512};
+use devp2p::DevP2PConfig;
+use devp2p::dpt::{DPTNode, DPTStream, DPTMessage};
+use devp2p::rlpx::{RLPxSendMessage, RLPxReceiveMessage, CapabilityInfo};
+use futures::{Stream, Sink, Future, Poll, Async, StartSend};
+use hexutil::read_hex;
+use rand::{thread_rng, Rng};
+use rand::os::OsRng;
+use secp256k1::SECP256K1;
+use secp256k1::key::{SecretKey, PublicKey};
+use tokio_core::reactor::{Handle, Timeout};
+
+use self::rlpx::RLPxStream;
+
+use std::cmp::min;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Read the node key from `path`, a file holding the hex-encoded
+/// secret key. If the file does not exist and `create` is set, a new
+/// key is generated and written there, readable only by the owner, so
+/// that the node keeps its enode ID across restarts.
+pub fn node_key(path: &Path, create: bool) -> Result<SecretKey, String> {
+    if !path.exists() && create {
+        let secret_key = SecretKey::new(&SECP256K1, &mut OsRng::new().map_err(|e| e.to_string())?);
+        OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+            .and_then(|mut file| file.write_all(format!("{:x}", H256::from(&secret_key[..])).as_bytes()))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Ok(secret_key);
+    }
+
+    let mut content = String::new();
+    File::open(path).and_then(|mut file| file.read_to_string(&mut content))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    read_hex(content.trim()).ok()
+        .and_then(|bytes| SecretKey::from_slice(&SECP256K1, &bytes).ok())
+        .ok_or(format!("{}: invalid node key", path.display()))
+}
+
+/// The node ID peers know us by.
+pub fn node_id(secret_key: &SecretKey) -> H512 {
+    let public_key = PublicKey::from_secret_key(&SECP256K1, secret_key).unwrap();
+    H512::from(&public_key.serialize_vec(&SECP256K1, false)[1..])
+}
+
+/// Peer discovery and RLPx connections, the same as
+/// `devp2p::DevP2PStream` except that the discovery table is kept
+/// accessible, so that the addresses of connected peers can be
+/// remembered across restarts, and that static nodes can be added.
+///
+/// Static nodes are dialed regardless of how many peers are
+/// connected: again as soon as they disconnect, and on every
+/// optimal peers check while they are not connected. Connections
+/// are tracked from the events of our own `RLPxStream`, which unlike
+/// rlpx 0.3 can dial a peer again after a clean disconnect.
+pub struct P2PStream {
+    dpt: DPTStream,
+    rlpx: RLPxStream,
+    static_nodes: Vec<DPTNode>,
+    /// Peers with a completed handshake, in either direction.
+    connected: HashSet<H512>,
+
+    ping_timeout: Timeout,
+    optimal_peers_timeout: Timeout,
+    handle: Handle,
+
+    config: DevP2PConfig,
+}
+
+impl P2PStream {
+    pub fn new(addr: &SocketAddr, public_addr: &IpAddr, handle: &Handle, secret_key: SecretKey,
+               protocol_version: usize, client_version: String, capabilities: Vec<CapabilityInfo>,
+               bootstrap_nodes: Vec<DPTNode>, config: DevP2PConfig) -> Result<Self, io::Error> {
+        let rlpx = RLPxStream::new(handle, secret_key.clone(), protocol_version, client_version,
+                                   capabilities, if config.listen { Some(addr) } else { None })?;
+        let dpt = DPTStream::new(addr, handle, secret_key, bootstrap_nodes, public_addr, addr.port())?;
+
+        Ok(P2PStream {
+            dpt, rlpx,
+            static_nodes: Vec::new(),
+            connected: HashSet::new(),
+            ping_timeout: Timeout::new(config.ping_interval, handle)?,
+            optimal_peers_timeout: Timeout::new(config.optimal_peers_interval, handle)?,
+            handle: handle.clone(),
+            config,
+        })
+    }
+
+    /// Address of a peer found through discovery. Peers that connected
+    /// to us are only known once discovery has found them too.
+    pub fn peer(&self, id: H512) -> Option<DPTNode> {
+        self.dpt.get_peer(id)
+    }
+
+    /// Keep a connection to `node` open at all times.
+    pub fn add_static_node(&mut self, node: DPTNode) {
+        self.rlpx.add_peer(&node.tcp_addr(), node.id);
+        self.static_nodes.push(node);
+    }
+
+    fn dial_static_nodes(&mut self) {
+        for node in &self.static_nodes {
+            if !self.connected.contains(&node.id) {
+                self.rlpx.add_peer(&node.tcp_addr(), node.id);
+            }
+        }
+    }
+
+    pub fn disconnect_peer(&mut self, id: H512) {
+        self.rlpx.disconnect_peer(id);
+        self.dpt.disconnect_peer(id);
+    }
+
+    /// Connect to peers found by discovery.
+    fn poll_discovered(&mut self) -> Result<(), io::Error> {
+        loop {
+            match self.dpt.poll()? {
+                Async::Ready(Some(node)) => self.rlpx.add_peer(&node.tcp_addr(), node.id),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Look for new peers and reconnect to old ones while we have
+    /// fewer than the optimal number.
+    fn poll_optimal_peers(&mut self) -> Result<(), io::Error> {
+        while let Async::Ready(()) = self.optimal_peers_timeout.poll()? {
+            self.dial_static_nodes();
+            let active = self.rlpx.active_peers().len();
+            if active < self.config.optimal_peers_len {
+                self.dpt.start_send(DPTMessage::RequestNewPeer)?;
+                self.dpt.poll_complete()?;
+
+                let mut connected = self.dpt.connected_peers().to_vec();
+                thread_rng().shuffle(&mut connected);
+                let count = min(self.config.optimal_peers_len - active,
+                                connected.len() / self.config.reconnect_dividend);
+                for node in &connected[..count] {
+                    self.rlpx.add_peer(&node.tcp_addr(), node.id);
+                }
+            }
+            self.optimal_peers_timeout = Timeout::new(self.config.optimal_peers_interval, &self.handle)?;
+        }
+        Ok(())
+    }
+
+    fn poll_ping(&mut self) -> Result<(), io::Error> {
+        while let Async::Ready(()) = self.ping_timeout.poll()? {
+            let timeout = Timeout::new(self.config.ping_timeout_interval, &self.handle)?;
+            self.dpt.start_send(DPTMessage::Ping(timeout))?;
+            self.dpt.poll_complete()?;
+            self.ping_timeout = Timeout::new(self.config.ping_interval, &self.handle)?;
+        }
+        Ok(())
+    }
+}
+
+impl Stream for P2PStream {
+    type Item = RLPxReceiveMessage;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.poll_discovered()?;
+        let result = self.rlpx.poll()?;
+        match result {
+            Async::Ready(Some(RLPxReceiveMessage::Connected { node, .. })) => {
+                self.connected.insert(node);
+            },
+            Async::Ready(Some(RLPxReceiveMessage::Disconnected { node })) => {
+                self.connected.remove(&node);
+                if self.static_nodes.iter().any(|static_node| static_node.id == node) {
+                    self.dial_static_nodes();
+                }
+            },
+            _ => (),
+        }
+        self.poll_optimal_peers()?;
+        self.poll_ping()?;
+        Ok(result)
+    }
+}
+
+impl Sink for P2PStream {
+    type SinkItem = RLPxSendMessage;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, val: RLPxSendMessage) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.poll_discovered()?;
+        let result = self.rlpx.start_send(val)?;
+        self.poll_optimal_peers()?;
+        self.poll_ping()?;
+        Ok(result)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.dpt.poll_complete());
+        try_ready!(self.rlpx.poll_complete());
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn node_key_roundtrip() {
+        let path = env::temp_dir().join(format!("node-key-{:x}", thread_rng().gen::<u64>()));
+        assert!(node_key(&path, false).is_err());
+
+        let secret_key = node_key(&path, true).unwrap();
+        let mut content = String::new();
+        File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content.len(), 64);
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(node_key(&path, true).unwrap(), secret_key);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+use bigint::{H256, U256};
+use block::{Header, Transaction, Block, Receipt};
+use rlp::{Encodable, RlpStream, DecoderError, UntrustedRlp};
+
+/// Highest eth version spoken. eth/62 is still spoken with peers that
+/// do not know eth/63.
+pub const PROTOCOL_VERSION: usize = 63;
+/// Number of message ids reserved by eth/63.
+pub const MESSAGE_COUNT: usize = 17;
+/// Number of message ids reserved by eth/62.
+pub const ETH62_MESSAGE_COUNT: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// eth/62 and eth/63 messages. This is `devp2p::ETHMessage` with the
+/// eth/63 state and receipt messages added, and with block bodies
+/// encoded as a list as the protocol requires.
+pub enum ETHMessage {
+    Status {
+        protocol_version: usize,
+        network_id: usize,
+        total_difficulty: U256,
+        best_hash: H256,
+        genesis_hash: H256,
+    },
+    NewBlockHashes(Vec<(H256, U256)>),
+    Transactions(Vec<Transaction>),
+    GetBlockHeadersByNumber {
+        number: U256,
+        max_headers: usize,
+        skip: usize,
+        reverse: bool,
+    },
+    GetBlockHeadersByHash {
+        hash: H256,
+        max_headers: usize,
+        skip: usize,
+        reverse: bool,
+    },
+    BlockHeaders(Vec<Header>),
+    GetBlockBodies(Vec<H256>),
+    BlockBodies(Vec<(Vec<Transaction>, Vec<Header>)>),
+    NewBlock {
+        block: Block,
+        total_difficulty: U256,
+    },
+    /// Hashes of state trie nodes or contract codes. eth/63 only.
+    GetNodeData(Vec<H256>),
+    /// Raw trie nodes or codes, in request order with missing ones
+    /// left out.
+    NodeData(Vec<Vec<u8>>),
+    /// Block hashes. eth/63 only.
+    GetReceipts(Vec<H256>),
+    Receipts(Vec<Vec<Receipt>>),
+    Unknown,
+}
+
+impl ETHMessage {
+    pub fn id(&self) -> usize {
+        match self {
+            &ETHMessage::Status { .. } => 0x00,
+            &ETHMessage::NewBlockHashes(_) => 0x01,
+            &ETHMessage::Transactions(_) => 0x02,
+            &ETHMessage::GetBlockHeadersByNumber { .. } => 0x03,
+            &ETHMessage::GetBlockHeadersByHash { .. } => 0x03,
+            &ETHMessage::BlockHeaders(_) => 0x04,
+            &ETHMessage::GetBlockBodies(_) => 0x05,
+            &ETHMessage::BlockBodies(_) => 0x06,
+            &ETHMessage::NewBlock { .. } => 0x07,
+            &ETHMessage::GetNodeData(_) => 0x0d,
+            &ETHMessage::NodeData(_) => 0x0e,
+            &ETHMessage::GetReceipts(_) => 0x0f,
+            &ETHMessage::Receipts(_) => 0x10,
+            &ETHMessage::Unknown => 127,
+        }
+    }
+
+    /// Decode a message with the given id. Unknown messages decode as
+    /// `Unknown`.
+    pub fn decode(rlp: &UntrustedRlp, id: usize) -> Result<Self, DecoderError> {
+        Ok(match id {
+            0x00 => ETHMessage::Status {
+                protocol_version: rlp.val_at(0)?,
+                network_id: rlp.val_at(1)?,
+                total_difficulty: rlp.val_at(2)?,
+                best_hash: rlp.val_at(3)?,
+                genesis_hash: rlp.val_at(4)?,
+            },
+            0x01 => {
+                let mut hashes = Vec::new();
+                for pair in rlp.iter() {
+                    hashes.push((pair.val_at(0)?, pair.val_at(1)?));
+                }
+                ETHMessage::NewBlockHashes(hashes)
+            },
+            0x02 => ETHMessage::Transactions(rlp.as_list()?),
+            0x03 => {
+                let reverse: u32 = rlp.val_at(3)?;
+                if rlp.at(0)?.size() == 32 {
+                    ETHMessage::GetBlockHeadersByHash {
+                        hash: rlp.val_at(0)?,
+                        max_headers: rlp.val_at(1)?,
+                        skip: rlp.val_at(2)?,
+                        reverse: reverse != 0,
+                    }
+                } else {
+                    ETHMessage::GetBlockHeadersByNumber {
+                        number: rlp.val_at(0)?,
+                        max_headers: rlp.val_at(1)?,
+                        skip: rlp.val_at(2)?,
+                        reverse: reverse != 0,
+                    }
+                }
+            },
+            0x04 => ETHMessage::BlockHeaders(rlp.as_list()?),
+            0x05 => ETHMessage::GetBlockBodies(rlp.as_list()?),
+            0x06 => {
+                let mut bodies = Vec::new();
+                for body in rlp.iter() {
+                    bodies.push((body.list_at(0)?, body.list_at(1)?));
+                }
+                ETHMessage::BlockBodies(bodies)
+            },
+            0x07 => ETHMessage::NewBlock {
+                block: rlp.val_at(0)?,
+                total_difficulty: rlp.val_at(1)?,
+            },
+            0x0d => ETHMessage::GetNodeData(rlp.as_list()?),
+            0x0e => ETHMessage::NodeData(rlp.as_list()?),
+            0x0f => ETHMessage::GetReceipts(rlp.as_list()?),
+            0x10 => {
+                let mut receipts = Vec::new();
+                for block in rlp.iter() {
+                    receipts.push(block.as_list()?);
+                }
+                ETHMessage::Receipts(receipts)
+            },
+            _ => ETHMessage::Unknown,
+        })
+    }
+}
+
+impl Encodable for ETHMessage {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            &ETHMessage::Status {
+                protocol_version, network_id, total_difficulty, best_hash, genesis_hash
+            } => {
+                s.begin_list(5);
+                s.append(&protocol_version);
+                s.append(&network_id);
+                s.append(&total_difficulty);
+                s.append(&best_hash);
+                s.append(&genesis_hash);
+            },
+            &ETHMessage::NewBlockHashes(ref hashes) => {
+                s.begin_list(hashes.len());
+                for &(hash, number) in hashes {
+                    s.begin_list(2);
+                    s.append(&hash);
+                    s.append(&number);
+                }
+            },
+            &ETHMessage::Transactions(ref transactions) => {
+                s.append_list(transactions);
+            },
+            &ETHMessage::GetBlockHeadersByNumber { number, max_headers, skip, reverse } => {
+                s.begin_list(4);
+                s.append(&number);
+                s.append(&max_headers);
+                s.append(&skip);
+                s.append(&if reverse { 1u32 } else { 0u32 });
+            },
+            &ETHMessage::GetBlockHeadersByHash { hash, max_headers, skip, reverse } => {
+                s.begin_list(4);
+                s.append(&hash);
+                s.append(&max_headers);
+                s.append(&skip);
+                s.append(&if reverse { 1u32 } else { 0u32 });
+            },
+            &ETHMessage::BlockHeaders(ref headers) => {
+                s.append_list(headers);
+            },
+            &ETHMessage::GetBlockBodies(ref hashes) => {
+                s.append_list(hashes);
+            },
+            &ETHMessage::BlockBodies(ref bodies) => {
+                s.begin_list(bodies.len());
+                for &(ref transactions, ref ommers) in bodies {
+                    s.begin_list(2);
+                    s.append_list(transactions);
+                    s.append_list(ommers);
+                }
+            },
+            &ETHMessage::NewBlock { ref block, ref total_difficulty } => {
+                s.begin_list(2);
+                s.append(block);
+                s.append(total_difficulty);
+            },
+            &ETHMessage::GetNodeData(ref hashes) => {
+                s.append_list(hashes);
+            },
+            &ETHMessage::NodeData(ref values) => {
+                s.append_list(values);
+            },
+            &ETHMessage::GetReceipts(ref hashes) => {
+                s.append_list(hashes);
+            },
+            &ETHMessage::Receipts(ref receipts) => {
+                s.begin_list(receipts.len());
+                for block in receipts {
+                    s.append_list(block);
+                }
+            },
+            &ETHMessage::Unknown => {
+                s.begin_list(0);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigint::Gas;
+    use bloom::LogsBloom;
+    use rlp;
+
+    #[test]
+    fn roundtrip() {
+        let receipt = Receipt {
+            state_root: H256::from(1u64),
+            used_gas: Gas::from(21000u64),
+            logs_bloom: LogsBloom::new(),
+            logs: Vec::new(),
+        };
+        let messages = vec![
+            ETHMessage::GetBlockHeadersByHash {
+                hash: H256::from(2u64), max_headers: 192, skip: 0, reverse: false,
+            },
+            ETHMessage::BlockBodies(Vec::new()),
+            ETHMessage::BlockBodies(vec![(Vec::new(), Vec::new()), (Vec::new(), Vec::new())]),
+            ETHMessage::GetNodeData(vec![H256::from(3u64), H256::from(4u64)]),
+            ETHMessage::NodeData(vec![vec![0xc2, 0x01, 0x02], vec![0x60, 0x00]]),
+            ETHMessage::GetReceipts(vec![H256::from(5u64)]),
+            ETHMessage::Receipts(vec![vec![receipt.clone(), receipt], Vec::new()]),
+        ];
+        for message in messages {
+            assert_eq!(ETHMessage::decode(&UntrustedRlp::new(&rlp::encode(&message)), message.id()), Ok(message));
+        }
+    }
+}
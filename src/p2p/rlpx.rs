@@ -0,0 +1,279 @@
+use bigint::H512;
+use devp2p::rlpx::{PeerStream, RLPxNode, RLPxSendMessage, RLPxReceiveMessage, CapabilityInfo};
+use futures::{Future, Stream, Sink, Poll, Async, StartSend, AsyncSink};
+use rand::{thread_rng, Rng};
+use secp256k1::key::SecretKey;
+use tokio_core::net::{TcpListener, Incoming};
+use tokio_core::reactor::Handle;
+
+use std::io;
+use std::net::SocketAddr;
+
+type PeerFuture = Box<Future<Item = PeerStream, Error = io::Error>>;
+
+/// RLPx connections, the same as `devp2p::rlpx::RLPxStream` except
+/// that a peer is forgotten whenever its connection ends. rlpx 0.3
+/// only forgets peers whose connection failed with an error, and
+/// refuses to dial a peer it has not forgotten, so one that closed
+/// its connection cleanly or that we disconnected could never be
+/// dialed again.
+pub struct RLPxStream {
+    streams: Vec<PeerStream>,
+    dialing: Vec<(H512, PeerFuture)>,
+    accepting: Vec<PeerFuture>,
+    listener: Option<Incoming>,
+    /// Peers being dialed or connected to after being dialed.
+    active_peers: Vec<H512>,
+    newly_connected: Vec<(H512, Vec<CapabilityInfo>)>,
+    newly_disconnected: Vec<H512>,
+
+    secret_key: SecretKey,
+    protocol_version: usize,
+    client_version: String,
+    capabilities: Vec<CapabilityInfo>,
+    port: u16,
+    handle: Handle,
+}
+
+fn retain_mut<T, F: FnMut(&mut T) -> bool>(vec: &mut Vec<T>, mut f: F) {
+    let mut i = 0;
+    while i < vec.len() {
+        if f(&mut vec[i]) {
+            i += 1;
+        } else {
+            vec.remove(i);
+        }
+    }
+}
+
+fn forget(active_peers: &mut Vec<H512>, newly_disconnected: &mut Vec<H512>, id: H512) {
+    active_peers.retain(|peer| *peer != id);
+    newly_disconnected.push(id);
+}
+
+impl RLPxStream {
+    pub fn new(handle: &Handle, secret_key: SecretKey, protocol_version: usize,
+               client_version: String, capabilities: Vec<CapabilityInfo>,
+               listen: Option<&SocketAddr>) -> Result<Self, io::Error> {
+        Ok(RLPxStream {
+            streams: Vec::new(),
+            dialing: Vec::new(),
+            accepting: Vec::new(),
+            listener: match listen {
+                Some(addr) => Some(TcpListener::bind(addr, handle)?.incoming()),
+                None => None,
+            },
+            active_peers: Vec::new(),
+            newly_connected: Vec::new(),
+            newly_disconnected: Vec::new(),
+            secret_key, protocol_version, client_version, capabilities,
+            port: listen.map(|addr| addr.port()).unwrap_or(0),
+            handle: handle.clone(),
+        })
+    }
+
+    /// Dial a peer unless it is already dialed or connected.
+    pub fn add_peer(&mut self, addr: &SocketAddr, remote_id: H512) {
+        if self.active_peers.contains(&remote_id) {
+            return;
+        }
+        debug!("connecting to peer {}", remote_id);
+        let future = PeerStream::connect(addr, &self.handle, self.secret_key.clone(), remote_id,
+                                         self.protocol_version, self.client_version.clone(),
+                                         self.capabilities.clone(), self.port);
+        self.dialing.push((remote_id, future));
+        self.active_peers.push(remote_id);
+    }
+
+    pub fn disconnect_peer(&mut self, remote_id: H512) {
+        let before = self.streams.len();
+        self.streams.retain(|peer| peer.remote_id() != remote_id);
+        self.dialing.retain(|&(id, _)| id != remote_id);
+        self.active_peers.retain(|peer| *peer != remote_id);
+        if self.streams.len() < before {
+            self.newly_disconnected.push(remote_id);
+        }
+    }
+
+    pub fn active_peers(&self) -> &[H512] {
+        &self.active_peers
+    }
+
+    /// Finish handshakes with dialed and accepted peers.
+    fn poll_new_peers(&mut self) -> Result<(), io::Error> {
+        {
+            let streams = &mut self.streams;
+            let active_peers = &mut self.active_peers;
+            let newly_connected = &mut self.newly_connected;
+            retain_mut(&mut self.dialing, |&mut (remote_id, ref mut future)| {
+                match future.poll() {
+                    Ok(Async::NotReady) => true,
+                    Ok(Async::Ready(peer)) => {
+                        newly_connected.push((remote_id, peer.capabilities().to_vec()));
+                        streams.push(peer);
+                        false
+                    },
+                    Err(err) => {
+                        debug!("dialing peer {} failed: {}", remote_id, err);
+                        active_peers.retain(|peer| *peer != remote_id);
+                        false
+                    },
+                }
+            });
+        }
+
+        if let Some(listener) = self.listener.as_mut() {
+            while let Async::Ready(Some((stream, _))) = listener.poll()? {
+                self.accepting.push(PeerStream::incoming(
+                    stream, self.secret_key.clone(), self.protocol_version,
+                    self.client_version.clone(), self.capabilities.clone(), self.port));
+            }
+        }
+
+        let streams = &mut self.streams;
+        let newly_connected = &mut self.newly_connected;
+        retain_mut(&mut self.accepting, |future| {
+            match future.poll() {
+                Ok(Async::NotReady) => true,
+                Ok(Async::Ready(peer)) => {
+                    newly_connected.push((peer.remote_id(), peer.capabilities().to_vec()));
+                    streams.push(peer);
+                    false
+                },
+                Err(err) => {
+                    debug!("incoming peer failed: {}", err);
+                    false
+                },
+            }
+        });
+        Ok(())
+    }
+
+    fn next_event(&mut self) -> Option<RLPxReceiveMessage> {
+        if let Some((node, capabilities)) = self.newly_connected.pop() {
+            return Some(RLPxReceiveMessage::Connected { node, capabilities });
+        }
+        self.newly_disconnected.pop().map(|node| RLPxReceiveMessage::Disconnected { node })
+    }
+}
+
+impl Stream for RLPxStream {
+    type Item = RLPxReceiveMessage;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.poll_new_peers()?;
+        if let Some(event) = self.next_event() {
+            return Ok(Async::Ready(Some(event)));
+        }
+
+        let mut ret = None;
+        {
+            let active_peers = &mut self.active_peers;
+            let newly_disconnected = &mut self.newly_disconnected;
+            retain_mut(&mut self.streams, |peer| {
+                if ret.is_some() {
+                    return true;
+                }
+                let node = peer.remote_id();
+                match peer.poll() {
+                    Ok(Async::NotReady) => true,
+                    Ok(Async::Ready(Some((capability, id, data)))) => {
+                        ret = Some(RLPxReceiveMessage::Normal { node, capability, id, data });
+                        true
+                    },
+                    Ok(Async::Ready(None)) => {
+                        debug!("peer {} closed the connection", node);
+                        forget(active_peers, newly_disconnected, node);
+                        false
+                    },
+                    Err(err) => {
+                        debug!("peer {} disconnected with error {}", node, err);
+                        forget(active_peers, newly_disconnected, node);
+                        false
+                    },
+                }
+            });
+        }
+
+        match ret {
+            Some(message) => Ok(Async::Ready(Some(message))),
+            None => Ok(self.next_event().map(|event| Async::Ready(Some(event))).unwrap_or(Async::NotReady)),
+        }
+    }
+}
+
+impl Sink for RLPxStream {
+    type SinkItem = RLPxSendMessage;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, message: RLPxSendMessage) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.poll_new_peers()?;
+        if message.node == RLPxNode::Any {
+            thread_rng().shuffle(&mut self.streams);
+        }
+
+        let mut any_ready = false;
+        {
+            let active_peers = &mut self.active_peers;
+            let newly_disconnected = &mut self.newly_disconnected;
+            retain_mut(&mut self.streams, |peer| {
+                let node = peer.remote_id();
+                let selected = match message.node {
+                    RLPxNode::Peer(id) => id == node,
+                    RLPxNode::All => true,
+                    RLPxNode::Any => !any_ready,
+                };
+                if !selected {
+                    return true;
+                }
+                match peer.start_send((message.capability_name, message.id, message.data.clone())) {
+                    Ok(AsyncSink::Ready) => {
+                        any_ready = true;
+                        true
+                    },
+                    Ok(AsyncSink::NotReady(_)) => true,
+                    Err(err) => {
+                        debug!("peer {} disconnected with error {}", node, err);
+                        forget(active_peers, newly_disconnected, node);
+                        false
+                    },
+                }
+            });
+        }
+
+        if any_ready {
+            Ok(AsyncSink::Ready)
+        } else {
+            Ok(AsyncSink::NotReady(message))
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        let mut all_ready = true;
+        {
+            let active_peers = &mut self.active_peers;
+            let newly_disconnected = &mut self.newly_disconnected;
+            retain_mut(&mut self.streams, |peer| {
+                match peer.poll_complete() {
+                    Ok(Async::Ready(())) => true,
+                    Ok(Async::NotReady) => {
+                        all_ready = false;
+                        true
+                    },
+                    Err(err) => {
+                        debug!("peer {} disconnected with error {}", peer.remote_id(), err);
+                        forget(active_peers, newly_disconnected, peer.remote_id());
+                        false
+                    },
+                }
+            });
+        }
+
+        if all_ready {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use bigint::{H256, M256, Address};
+use block::Account;
+use db::ChainDatabase;
+use rlp::{UntrustedRlp, DecoderError};
+use sha3::{Digest, Keccak256};
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Reason a Merkle-Patricia proof could not be verified.
+pub enum ProofError {
+    /// The block the proof should be checked against is not known.
+    UnknownBlock(H256),
+    /// A node on the path to the key is not part of the proof, or
+    /// the proof does not match the root.
+    MissingNode(H256),
+    /// The node with the hash, or a node inlined in it, is not a
+    /// valid trie node.
+    InvalidNode(H256),
+    /// The proven value does not decode to the expected type.
+    InvalidValue,
+}
+
+enum NodeRef<'a> {
+    Hash(H256),
+    Inline(&'a [u8]),
+}
+
+pub fn keccak(data: &[u8]) -> H256 {
+    H256::from(Keccak256::digest(data).as_slice())
+}
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(key.len() * 2);
+    for byte in key {
+        ret.push(byte >> 4);
+        ret.push(byte & 0x0f);
+    }
+    ret
+}
+
+/// Decode a hex-prefix encoded node path into its nibbles and
+/// whether it belongs to a leaf.
+fn decode_path(data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    if data.len() == 0 {
+        return None;
+    }
+
+    let flag = data[0] >> 4;
+    if flag > 3 {
+        return None;
+    }
+    let mut path = nibbles(data);
+    if flag & 1 == 1 {
+        path.remove(0);
+    } else {
+        if data[0] & 0x0f != 0 {
+            return None;
+        }
+        path.drain(0..2);
+    }
+
+    Some((path, flag & 2 == 2))
+}
+
+fn child<'a>(rlp: &UntrustedRlp<'a>) -> Option<Option<NodeRef<'a>>> {
+    if rlp.is_list() {
+        if rlp.as_raw().len() < 32 { Some(Some(NodeRef::Inline(rlp.as_raw()))) } else { None }
+    } else if rlp.is_empty() {
+        Some(None)
+    } else {
+        match rlp.as_val::<H256>() {
+            Ok(hash) if rlp.size() == 32 => Some(Some(NodeRef::Hash(hash))),
+            _ => None,
+        }
+    }
+}
+
+/// Hashes of the nodes referenced by a trie node and the values it
+/// stores, looking into nodes inlined in it. Returns `None` if the
+/// data is not a valid trie node.
+pub fn node_references(data: &[u8]) -> Option<(Vec<H256>, Vec<&[u8]>)> {
+    let mut hashes = Vec::new();
+    let mut values = Vec::new();
+    let mut nodes = vec![data];
+
+    while let Some(data) = nodes.pop() {
+        let rlp = UntrustedRlp::new(data);
+        let mut children = Vec::new();
+        match rlp.item_count() {
+            Ok(2) if rlp.is_list() => {
+                let (_, is_leaf) = decode_path(rlp.at(0).and_then(|r| r.data()).ok()?)?;
+                if is_leaf {
+                    values.push(rlp.at(1).and_then(|r| r.data()).ok()?);
+                } else {
+                    children.push(child(&rlp.at(1).ok()?)?);
+                }
+            },
+            Ok(17) if rlp.is_list() => {
+                for index in 0..16 {
+                    children.push(child(&rlp.at(index).ok()?)?);
+                }
+                let value = rlp.at(16).and_then(|r| r.data()).ok()?;
+                if value.len() > 0 {
+                    values.push(value);
+                }
+            },
+            _ => return None,
+        }
+
+        for reference in children {
+            match reference {
+                Some(NodeRef::Hash(hash)) => hashes.push(hash),
+                Some(NodeRef::Inline(data)) => nodes.push(data),
+                None => (),
+            }
+        }
+    }
+
+    Some((hashes, values))
+}
+
+/// Look up `key` in the trie with the given root using only the
+/// nodes in `proof`. Returns the raw value, or `None` if the proof
+/// shows the key is absent.
+pub fn verify_proof(root: H256, key: &[u8], proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>, ProofError> {
+    if root == keccak(&[0x80]) {
+        return Ok(None);
+    }
+
+    let nodes: HashMap<H256, &[u8]> = proof.iter()
+        .map(|node| (keccak(node), node.as_slice()))
+        .collect();
+    let key = nibbles(key);
+    let mut remaining: &[u8] = &key;
+    let mut hash = root;
+    let mut current = NodeRef::Hash(root);
+
+    loop {
+        let data = match current {
+            NodeRef::Hash(h) => {
+                hash = h;
+                match nodes.get(&h) {
+                    Some(data) => *data,
+                    None => return Err(ProofError::MissingNode(h)),
+                }
+            },
+            NodeRef::Inline(data) => data,
+        };
+        let invalid = move |_: DecoderError| ProofError::InvalidNode(hash);
+        let rlp = UntrustedRlp::new(data);
+        if !rlp.is_list() {
+            return Err(ProofError::InvalidNode(hash));
+        }
+
+        let next = match rlp.item_count().map_err(invalid)? {
+            2 => {
+                let (path, is_leaf) = decode_path(rlp.at(0).and_then(|r| r.data()).map_err(invalid)?)
+                    .ok_or(ProofError::InvalidNode(hash))?;
+                if is_leaf {
+                    return if remaining == path.as_slice() {
+                        Ok(Some(rlp.at(1).and_then(|r| r.data()).map_err(invalid)?.to_vec()))
+                    } else {
+                        Ok(None)
+                    };
+                }
+                if path.len() == 0 || !remaining.starts_with(&path) {
+                    return Ok(None);
+                }
+                remaining = &remaining[path.len()..];
+                child(&rlp.at(1).map_err(invalid)?).ok_or(ProofError::InvalidNode(hash))?
+            },
+            17 => {
+                if remaining.len() == 0 {
+                    let value = rlp.at(16).and_then(|r| r.data()).map_err(invalid)?;
+                    return Ok(if value.len() == 0 { None } else { Some(value.to_vec()) });
+                }
+                let index = remaining[0] as usize;
+                remaining = &remaining[1..];
+                child(&rlp.at(index).map_err(invalid)?).ok_or(ProofError::InvalidNode(hash))?
+            },
+            _ => return Err(ProofError::InvalidNode(hash)),
+        };
+
+        current = match next {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+    }
+}
+
+/// Verify an account proof against a state root. Returns `None` if
+/// the proof shows the account does not exist.
+pub fn verify_account(state_root: H256, address: Address, proof: &[Vec<u8>]) -> Result<Option<Account>, ProofError> {
+    match verify_proof(state_root, keccak(address.as_ref()).as_ref(), proof)? {
+        Some(raw) => UntrustedRlp::new(&raw).as_val().map(Some).map_err(|_| ProofError::InvalidValue),
+        None => Ok(None),
+    }
+}
+
+/// Verify a storage proof against an account's storage root. Absent
+/// slots are zero.
+pub fn verify_storage(storage_root: H256, index: H256, proof: &[Vec<u8>]) -> Result<M256, ProofError> {
+    match verify_proof(storage_root, keccak(index.as_ref()).as_ref(), proof)? {
+        Some(raw) => UntrustedRlp::new(&raw).as_val().map_err(|_| ProofError::InvalidValue),
+        None => Ok(M256::zero()),
+    }
+}
+
+/// Verify an account proof against the state root of a block whose
+/// header is already validated.
+pub fn account_at(
+    chain: &ChainDatabase, block: H256, address: Address, proof: &[Vec<u8>]
+) -> Result<Option<Account>, ProofError> {
+    let header = chain.header(block).ok_or(ProofError::UnknownBlock(block))?;
+    verify_account(header.state_root, address, proof)
+}
+
+/// Verify a storage slot of an account at a validated block, given
+/// proofs for both the account and the slot.
+pub fn storage_at(
+    chain: &ChainDatabase, block: H256, address: Address, account_proof: &[Vec<u8>],
+    index: H256, storage_proof: &[Vec<u8>]
+) -> Result<M256, ProofError> {
+    match account_at(chain, block, address, account_proof)? {
+        Some(account) => verify_storage(account.storage_root, index, storage_proof),
+        None => Ok(M256::zero()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigint::U256;
+    use trie::{DatabaseGuard, FixedSecureTrie};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Nodes(Rc<RefCell<HashMap<H256, Vec<u8>>>>);
+
+    impl DatabaseGuard for Nodes {
+        fn get(&self, hash: H256) -> Option<Vec<u8>> {
+            self.0.borrow().get(&hash).cloned()
+        }
+
+        fn set(&mut self, hash: H256, value: Vec<u8>) {
+            self.0.borrow_mut().insert(hash, value);
+        }
+    }
+
+    fn account(balance: u64) -> Account {
+        Account {
+            nonce: U256::zero(),
+            balance: U256::from(balance),
+            storage_root: keccak(&[0x80]),
+            code_hash: keccak(&[]),
+        }
+    }
+
+    #[test]
+    fn verify_accounts() {
+        let nodes = Nodes::default();
+        let mut state: FixedSecureTrie<Nodes, Address, Account> = FixedSecureTrie::empty(nodes.clone());
+        for i in 1..100 {
+            state.insert(Address::from(i), account(i));
+        }
+        let root = state.root();
+        // All nodes of the trie together form a superset of any proof.
+        let proof: Vec<Vec<u8>> = nodes.0.borrow().values().cloned().collect();
+
+        assert_eq!(verify_account(root, Address::from(42u64), &proof), Ok(Some(account(42))));
+        assert_eq!(verify_account(root, Address::from(500u64), &proof), Ok(None));
+        assert_eq!(verify_account(keccak(&[0x80]), Address::from(42u64), &[]), Ok(None));
+        assert_eq!(verify_account(root, Address::from(42u64), &[]), Err(ProofError::MissingNode(root)));
+        assert_eq!(verify_account(root, Address::from(42u64), &[vec![0x01]]),
+                   Err(ProofError::MissingNode(root)));
+
+        let garbage = vec![0xc2, 0x01, 0x02];
+        let garbage_hash = keccak(&garbage);
+        assert_eq!(verify_account(garbage_hash, Address::from(42u64), &[garbage]),
+                   Err(ProofError::InvalidNode(garbage_hash)));
+    }
+}
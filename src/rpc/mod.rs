@@ -0,0 +1,500 @@
+mod serialize;
+
+use self::serialize::*;
+
+use jsonrpc_core::{IoHandler, Params, Value, Error, ErrorCode};
+use jsonrpc_http_server::{ServerBuilder, Server};
+use serde_json;
+use bigint::{H256, U256, M256, Gas};
+use block::{Block, Header, Account, Transaction, RlpHash};
+use rlp::UntrustedRlp;
+use futures::Future;
+use futures::sync::mpsc::UnboundedSender;
+use futures::sync::oneshot;
+use blockchain::chain::HeaderHash;
+use sputnikvm_stateful::Stateful;
+use hexutil::to_hex;
+use db::{ChainDatabase, StateDatabase};
+use spec::ChainSpec;
+use validator::{self, CallRequest};
+use filter::{LogFilter, filter_logs};
+use txpool;
+use les::{LightQuery, LightRequest, LightResponse};
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Access to the synced chain. Everything is read straight from the
+/// databases, so the server can run on its own threads while the
+/// processor keeps importing blocks. Submitted transactions are
+/// checked here and then handed to the sync loop through
+/// `transactions` for pooling and propagation. With `light` set,
+/// state that is not stored locally is requested from LES servers and
+/// verified against the local headers.
+pub struct EthereumRPC {
+    spec: ChainSpec,
+    chain: ChainDatabase,
+    state: StateDatabase,
+    transactions: UnboundedSender<Transaction>,
+    light: Option<UnboundedSender<LightQuery>>,
+    client_version: String,
+}
+
+impl EthereumRPC {
+    pub fn new(spec: ChainSpec, chain: ChainDatabase, state: StateDatabase,
+               transactions: UnboundedSender<Transaction>, light: Option<UnboundedSender<LightQuery>>,
+               client_version: String) -> Self {
+        Self { spec, chain, state, transactions, light, client_version }
+    }
+
+    /// Whether the state at the header has to be requested from LES
+    /// servers.
+    fn needs_light(&self, header: &Header) -> bool {
+        self.light.is_some() && self.state.node(header.state_root).is_none()
+    }
+
+    /// Send a request to LES servers and wait for the verified
+    /// response.
+    fn light_query(&self, request: LightRequest) -> Result<LightResponse, Error> {
+        let (reply, response) = oneshot::channel();
+        self.light.as_ref().ok_or(Error::internal_error())?
+            .unbounded_send(LightQuery { request, reply })
+            .map_err(|_| Error::internal_error())?;
+
+        match response.wait() {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(err)) => Err(Error {
+                code: ErrorCode::ServerError(-32000),
+                message: format!("light request failed: {:?}", err),
+                data: None,
+            }),
+            Err(_) => Err(Error::internal_error()),
+        }
+    }
+
+    /// Fail unless the state at the header is stored locally. Without
+    /// it, executing against the state would see every account as
+    /// empty.
+    fn require_state(&self, header: &Header) -> Result<(), Error> {
+        if self.state.node(header.state_root).is_none() {
+            return Err(Error {
+                code: ErrorCode::ServerError(-32000),
+                message: format!("state of block {} is not available", header.number),
+                data: None,
+            });
+        }
+        Ok(())
+    }
+
+    fn best_header(&self) -> Header {
+        self.chain.header(self.chain.best_hash().unwrap()).unwrap()
+    }
+
+    /// Resolve a block tag ("latest", "earliest", "pending" or a
+    /// number) into a block number.
+    fn number_by_tag(&self, tag: &str) -> Result<U256, Error> {
+        match tag {
+            "latest" | "pending" => Ok(self.best_header().number),
+            "earliest" => Ok(U256::zero()),
+            _ => from_quantity(tag),
+        }
+    }
+
+    /// Resolve a block tag into a canonical header.
+    fn header_by_tag(&self, tag: &str) -> Result<Header, Error> {
+        self.chain.canonical_hash(self.number_by_tag(tag)?)
+            .and_then(|hash| self.chain.header(hash))
+            .ok_or(Error::invalid_params(format!("unknown block: {}", tag)))
+    }
+
+    fn account(&self, address: &str, tag: &str) -> Result<Option<Account>, Error> {
+        let address = from_address(address)?;
+        let header = self.header_by_tag(tag)?;
+        if self.needs_light(&header) {
+            return match self.light_query(LightRequest::Account { block: header.header_hash(), address })? {
+                LightResponse::Account(account) => Ok(account),
+                _ => Err(Error::internal_error()),
+            };
+        }
+        let stateful = Stateful::new(&self.state, header.state_root);
+        let account = stateful.state().get(&address);
+        Ok(account)
+    }
+
+    fn block(&self, hash: H256, full: bool) -> Result<Value, Error> {
+        let (header, total_difficulty) = match self.chain.fetch(hash) {
+            Some(val) => val,
+            None => return Ok(Value::Null),
+        };
+        let (transactions, ommers) = self.chain.body(hash).unwrap_or((Vec::new(), Vec::new()));
+        let block = Block { header, transactions, ommers };
+
+        Ok(serde_json::to_value(RPCBlock::new(&block, total_difficulty, full)).unwrap())
+    }
+
+    pub fn block_number(&self) -> Result<Value, Error> {
+        Ok(Value::String(to_quantity(self.best_header().number)))
+    }
+
+    pub fn get_block_by_number(&self, tag: String, full: bool) -> Result<Value, Error> {
+        match self.chain.canonical_hash(self.number_by_tag(&tag)?) {
+            Some(hash) => self.block(hash, full),
+            None => Ok(Value::Null),
+        }
+    }
+
+    pub fn get_block_by_hash(&self, hash: String, full: bool) -> Result<Value, Error> {
+        self.block(from_hash(&hash)?, full)
+    }
+
+    pub fn get_balance(&self, address: String, tag: String) -> Result<Value, Error> {
+        let balance = self.account(&address, &tag)?.map(|a| a.balance).unwrap_or(U256::zero());
+        Ok(Value::String(to_quantity(balance)))
+    }
+
+    pub fn get_transaction_count(&self, address: String, tag: String) -> Result<Value, Error> {
+        let nonce = self.account(&address, &tag)?.map(|a| a.nonce).unwrap_or(U256::zero());
+        Ok(Value::String(to_quantity(nonce)))
+    }
+
+    pub fn get_code(&self, address: String, tag: String) -> Result<Value, Error> {
+        let header = self.header_by_tag(&tag)?;
+        if self.needs_light(&header) {
+            let request = LightRequest::Code { block: header.header_hash(), address: from_address(&address)? };
+            return match self.light_query(request)? {
+                LightResponse::Code(code) => Ok(Value::String(to_hex(&code))),
+                _ => Err(Error::internal_error()),
+            };
+        }
+
+        let code = match self.account(&address, &tag)? {
+            Some(account) => {
+                let stateful = Stateful::new(&self.state, H256::default());
+                stateful.code(account.code_hash).unwrap_or(Vec::new())
+            },
+            None => Vec::new(),
+        };
+        Ok(Value::String(to_hex(&code)))
+    }
+
+    pub fn get_storage_at(&self, address: String, index: String, tag: String) -> Result<Value, Error> {
+        let header = self.header_by_tag(&tag)?;
+        if self.needs_light(&header) {
+            let request = LightRequest::Storage {
+                block: header.header_hash(),
+                address: from_address(&address)?,
+                index: H256::from(from_quantity(&index)?),
+            };
+            return match self.light_query(request)? {
+                LightResponse::Storage(value) => Ok(Value::String(to_hash(H256::from(value)))),
+                _ => Err(Error::internal_error()),
+            };
+        }
+
+        let value = match self.account(&address, &tag)? {
+            Some(account) => {
+                let stateful = Stateful::new(&self.state, H256::default());
+                let storage = stateful.storage_state_of(account.storage_root);
+                storage.get(&H256::from(from_quantity(&index)?)).unwrap_or(M256::zero())
+            },
+            None => M256::zero(),
+        };
+        Ok(Value::String(to_hash(H256::from(value))))
+    }
+
+    pub fn net_version(&self) -> Result<Value, Error> {
+        Ok(Value::String(format!("{}", self.spec.network_id)))
+    }
+
+    fn call_request(&self, call: RPCCall, header: &Header) -> Result<CallRequest, Error> {
+        Ok(CallRequest {
+            caller: match call.from {
+                Some(ref from) => Some(from_address(from)?),
+                None => None,
+            },
+            to: match call.to {
+                Some(ref to) => Some(from_address(to)?),
+                None => None,
+            },
+            value: match call.value {
+                Some(ref value) => from_quantity(value)?,
+                None => U256::zero(),
+            },
+            data: match call.data {
+                Some(ref data) => from_data(data)?,
+                None => Vec::new(),
+            },
+            gas_limit: match call.gas {
+                Some(ref gas) => Gas::from(from_quantity(gas)?),
+                None => header.gas_limit,
+            },
+            gas_price: match call.gas_price {
+                Some(ref gas_price) => Gas::from(from_quantity(gas_price)?),
+                None => Gas::zero(),
+            },
+        })
+    }
+
+    pub fn call(&self, call: RPCCall, tag: Option<String>) -> Result<Value, Error> {
+        let header = self.header_by_tag(&tag.unwrap_or("latest".to_string()))?;
+        self.require_state(&header)?;
+        let request = self.call_request(call, &header)?;
+        let result = validator::call(&self.spec, &self.state, &self.chain, &header, &request);
+
+        if result.reverted {
+            return Err(Error {
+                code: ErrorCode::ServerError(-32015),
+                message: "VM execution error".to_string(),
+                data: Some(Value::String(to_hex(&result.output))),
+            });
+        }
+        Ok(Value::String(to_hex(&result.output)))
+    }
+
+    pub fn estimate_gas(&self, call: RPCCall, tag: Option<String>) -> Result<Value, Error> {
+        let header = self.header_by_tag(&tag.unwrap_or("latest".to_string()))?;
+        self.require_state(&header)?;
+        let request = self.call_request(call, &header)?;
+
+        match validator::estimate_gas(&self.spec, &self.state, &self.chain, &header, &request) {
+            Ok(gas) => Ok(Value::String(to_quantity(gas))),
+            Err(_) => Err(Error {
+                code: ErrorCode::ServerError(-32015),
+                message: format!("gas required exceeds allowance ({})", request.gas_limit),
+                data: None,
+            }),
+        }
+    }
+
+    pub fn get_transaction_receipt(&self, hash: String) -> Result<Value, Error> {
+        let (block_hash, index) = match self.chain.transaction_location(from_hash(&hash)?) {
+            Some(val) => val,
+            None => return Ok(Value::Null),
+        };
+        let header = self.chain.header(block_hash).unwrap();
+        let transactions = self.chain.body(block_hash).unwrap().0;
+        let receipts = match self.chain.receipts(block_hash) {
+            Some(val) => val,
+            None => return Ok(Value::Null),
+        };
+
+        Ok(serde_json::to_value(
+            RPCReceipt::new(&transactions[index], &header, index, &receipts)).unwrap())
+    }
+
+    fn log_filter(&self, filter: RPCLogFilter) -> Result<LogFilter, Error> {
+        fn hashes(value: Value) -> Result<Vec<String>, Error> {
+            match value {
+                Value::String(s) => Ok(vec![s]),
+                value => serde_json::from_value(value)
+                    .map_err(|e| Error::invalid_params(format!("{}", e))),
+            }
+        }
+
+        let mut addresses = Vec::new();
+        if let Some(address) = filter.address {
+            for address in hashes(address)? {
+                addresses.push(from_address(&address)?);
+            }
+        }
+
+        let mut topics = Vec::new();
+        for topic in filter.topics.unwrap_or(Vec::new()) {
+            if topic == Value::Null {
+                topics.push(None);
+            } else {
+                let mut options = Vec::new();
+                for option in hashes(topic)? {
+                    options.push(from_hash(&option)?);
+                }
+                topics.push(Some(options));
+            }
+        }
+
+        Ok(LogFilter {
+            from_block: self.header_by_tag(
+                &filter.from_block.unwrap_or("latest".to_string()))?.number,
+            to_block: self.header_by_tag(
+                &filter.to_block.unwrap_or("latest".to_string()))?.number,
+            addresses, topics,
+        })
+    }
+
+    pub fn get_logs(&self, filter: RPCLogFilter) -> Result<Value, Error> {
+        let filter = self.log_filter(filter)?;
+        let logs: Vec<RPCLog> = filter_logs(&self.chain, &filter).iter().map(RPCLog::new).collect();
+
+        Ok(serde_json::to_value(logs).unwrap())
+    }
+
+    pub fn send_raw_transaction(&self, data: String) -> Result<Value, Error> {
+        let data = from_data(&data)?;
+        let transaction: Transaction = UntrustedRlp::new(&data).as_val()
+            .map_err(|_| Error::invalid_params("invalid transaction RLP"))?;
+
+        let header = self.best_header();
+        self.require_state(&header)?;
+        txpool::validate(&self.spec, &self.state, &header, &transaction)
+            .map_err(|e| Error::invalid_params(format!("invalid transaction: {:?}", e)))?;
+
+        let hash = transaction.rlp_hash();
+        self.transactions.unbounded_send(transaction).map_err(|_| Error::internal_error())?;
+        Ok(Value::String(to_hash(hash)))
+    }
+
+    pub fn client_version(&self) -> Result<Value, Error> {
+        Ok(Value::String(self.client_version.clone()))
+    }
+}
+
+/// Parse the parameters of `eth_call` and `eth_estimateGas`: a call
+/// object followed by an optional block tag.
+fn call_params(params: Vec<Value>) -> Result<(RPCCall, Option<String>), Error> {
+    let mut params = params.into_iter();
+    let call = match params.next() {
+        Some(call) => serde_json::from_value(call)
+            .map_err(|e| Error::invalid_params(format!("{}", e)))?,
+        None => return Err(Error::invalid_params("missing call object")),
+    };
+    let tag = match params.next() {
+        Some(tag) => Some(serde_json::from_value(tag)
+                          .map_err(|e| Error::invalid_params(format!("{}", e)))?),
+        None => None,
+    };
+    Ok((call, tag))
+}
+
+/// Register all supported methods on a JSON-RPC handler.
+pub fn handler(rpc: Arc<EthereumRPC>) -> IoHandler {
+    let mut io = IoHandler::new();
+
+    macro_rules! method {
+        ($name:expr, |$rpc:ident| $body:expr) => {{
+            let $rpc = rpc.clone();
+            io.add_method($name, move |_: Params| $body);
+        }};
+        ($name:expr, |$rpc:ident, $params:ident: $ty:ty| $body:expr) => {{
+            let $rpc = rpc.clone();
+            io.add_method($name, move |params: Params| {
+                let $params: $ty = params.parse()?;
+                $body
+            });
+        }};
+    }
+
+    method!("web3_clientVersion", |rpc| rpc.client_version());
+    method!("net_version", |rpc| rpc.net_version());
+    method!("eth_blockNumber", |rpc| rpc.block_number());
+    method!("eth_getBlockByNumber", |rpc, p: (String, bool)| rpc.get_block_by_number(p.0, p.1));
+    method!("eth_getBlockByHash", |rpc, p: (String, bool)| rpc.get_block_by_hash(p.0, p.1));
+    method!("eth_getBalance", |rpc, p: (String, String)| rpc.get_balance(p.0, p.1));
+    method!("eth_getTransactionCount", |rpc, p: (String, String)| rpc.get_transaction_count(p.0, p.1));
+    method!("eth_getCode", |rpc, p: (String, String)| rpc.get_code(p.0, p.1));
+    method!("eth_getStorageAt", |rpc, p: (String, String, String)| rpc.get_storage_at(p.0, p.1, p.2));
+    method!("eth_call", |rpc, p: Vec<Value>| {
+        let (call, tag) = call_params(p)?;
+        rpc.call(call, tag)
+    });
+    method!("eth_getTransactionReceipt", |rpc, p: (String,)| rpc.get_transaction_receipt(p.0));
+    method!("eth_getLogs", |rpc, p: (RPCLogFilter,)| rpc.get_logs(p.0));
+    method!("eth_sendRawTransaction", |rpc, p: (String,)| rpc.send_raw_transaction(p.0));
+    method!("eth_estimateGas", |rpc, p: Vec<Value>| {
+        let (call, tag) = call_params(p)?;
+        rpc.estimate_gas(call, tag)
+    });
+
+    io
+}
+
+/// Start the HTTP JSON-RPC server. The server keeps running until
+/// the returned handle is dropped.
+pub fn start(addr: &SocketAddr, rpc: EthereumRPC) -> Result<Server, String> {
+    ServerBuilder::new(handler(Arc::new(rpc)))
+        .start_http(addr)
+        .map_err(|e| format!("{:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::{KeyValueStore, MemoryKeyValueStore};
+    use validator::EthereumProcessor;
+    use spec::ChainSpec;
+    use futures::sync::mpsc::unbounded;
+    use bigint::Address;
+    use block::{Log, Receipt};
+    use bloom::LogsBloom;
+    use testing::child;
+
+    #[test]
+    fn genesis_queries() {
+        let store: Arc<KeyValueStore> = Arc::new(MemoryKeyValueStore::new());
+        let processor = EthereumProcessor::new(store, ChainSpec::classic());
+        let (transactions, _) = unbounded();
+        let rpc = EthereumRPC::new(ChainSpec::classic(), processor.chain().clone(),
+                                   processor.state().clone(), transactions, None, "etclient".to_string());
+
+        assert_eq!(rpc.block_number().unwrap(), Value::String("0x0".to_string()));
+        assert_eq!(rpc.get_balance("0x000d836201318ec6899a67540690382780743280".to_string(),
+                                   "latest".to_string()).unwrap(),
+                   Value::String("0xad78ebc5ac6200000".to_string()));
+        assert_eq!(rpc.get_block_by_number("0x1".to_string(), false).unwrap(), Value::Null);
+        assert!(rpc.get_block_by_number("first".to_string(), false).is_err());
+        assert!(rpc.get_block_by_number("1".to_string(), false).is_err());
+        assert!(rpc.get_block_by_number(format!("0x1{}", "0".repeat(64)), false).is_err());
+        assert_eq!(rpc.get_logs(RPCLogFilter {
+            from_block: Some("earliest".to_string()), to_block: None,
+            address: None, topics: None,
+        }).unwrap(), Value::Array(Vec::new()));
+
+        let transfer = || RPCCall {
+            from: Some("0x000d836201318ec6899a67540690382780743280".to_string()),
+            to: Some("0x0000000000000000000000000000000000000001".to_string()),
+            gas: Some("0x100000".to_string()), gas_price: None,
+            value: Some("0x1".to_string()),
+            data: None,
+        };
+        assert_eq!(rpc.call(transfer(), None).unwrap(),
+                   Value::String("0x".to_string()));
+        assert_eq!(rpc.estimate_gas(transfer(), None).unwrap(),
+                   Value::String("0x5208".to_string()));
+        assert!(rpc.send_raw_transaction("0xc0".to_string()).is_err());
+    }
+
+    #[test]
+    fn logs_without_bodies() {
+        let store: Arc<KeyValueStore> = Arc::new(MemoryKeyValueStore::new());
+        let processor = EthereumProcessor::new(store, ChainSpec::classic());
+        let chain = processor.chain().clone();
+        let (transactions, _) = unbounded();
+        let rpc = EthereumRPC::new(ChainSpec::classic(), chain.clone(),
+                                   processor.state().clone(), transactions, None, "etclient".to_string());
+
+        // Header-only and fast sync store receipts but no bodies.
+        let address = Address::from(1u64);
+        let mut logs_bloom = LogsBloom::new();
+        logs_bloom.set(&address);
+        let receipts = vec![Receipt {
+            state_root: H256::default(),
+            used_gas: Gas::from(21000u64),
+            logs_bloom: logs_bloom.clone(),
+            logs: vec![Log { address, topics: Vec::new(), data: Vec::new() }],
+        }];
+        let mut header = child(&processor.best_header(), 0);
+        header.logs_bloom = logs_bloom;
+        let hash = header.header_hash();
+        chain.put_header(&header, U256::from(2u64));
+        chain.put_receipts(hash, &receipts);
+        chain.set_canonical_hash(header.number, hash);
+        chain.set_best_hash(hash);
+
+        let logs = rpc.get_logs(RPCLogFilter {
+            from_block: Some("earliest".to_string()), to_block: None,
+            address: Some(Value::String("0x0000000000000000000000000000000000000001".to_string())),
+            topics: None,
+        }).unwrap();
+        assert_eq!(logs[0]["blockHash"], Value::String(to_hash(hash)));
+        assert_eq!(logs[0]["transactionIndex"], Value::String("0x0".to_string()));
+        assert_eq!(logs[0]["transactionHash"], Value::Null);
+    }
+}
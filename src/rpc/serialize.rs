@@ -0,0 +1,266 @@
+use bigint::{H256, H2048, U256, Address};
+use block::{Block, Header, Transaction, Receipt, RlpHash, TransactionAction};
+use filter::LocalizedLog;
+use blockchain::chain::HeaderHash;
+use hexutil::*;
+use jsonrpc_core::{Error, Value};
+use rlp;
+
+use std::str::FromStr;
+
+pub fn to_quantity<T: Into<U256>>(value: T) -> String {
+    let value: U256 = value.into();
+    if value.is_zero() {
+        "0x0".to_string()
+    } else {
+        format!("0x{:x}", value)
+    }
+}
+
+pub fn to_hash<T: AsRef<[u8]>>(value: T) -> String {
+    to_hex(value.as_ref())
+}
+
+/// Parse a `0x`-prefixed hex quantity of at most 256 bits.
+pub fn from_quantity(value: &str) -> Result<U256, Error> {
+    let invalid = || Error::invalid_params(format!("invalid quantity: {}", value));
+    if !value.starts_with("0x") {
+        return Err(invalid());
+    }
+    let digits = &value[2..];
+    if digits.len() == 0 || digits.len() > 64 || !digits.chars().all(|c| c.is_digit(16)) {
+        return Err(invalid());
+    }
+    U256::from_str(digits).map_err(|_| invalid())
+}
+
+pub fn from_data(value: &str) -> Result<Vec<u8>, Error> {
+    read_hex(value).map_err(|_| Error::invalid_params(format!("invalid data: {}", value)))
+}
+
+pub fn from_hash(value: &str) -> Result<H256, Error> {
+    H256::from_str(value).map_err(|_| Error::invalid_params(format!("invalid hash: {}", value)))
+}
+
+pub fn from_address(value: &str) -> Result<Address, Error> {
+    Address::from_str(value).map_err(|_| Error::invalid_params(format!("invalid address: {}", value)))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RPCCall {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub gas: Option<String>,
+    pub gas_price: Option<String>,
+    pub value: Option<String>,
+    pub data: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RPCLogFilter {
+    pub from_block: Option<String>,
+    pub to_block: Option<String>,
+    /// Either a single address or a list of addresses.
+    pub address: Option<Value>,
+    /// Each entry is either null, a single topic or a list of topics.
+    pub topics: Option<Vec<Value>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RPCLog {
+    pub removed: bool,
+    pub log_index: String,
+    pub transaction_index: String,
+    pub transaction_hash: Option<String>,
+    pub block_hash: String,
+    pub block_number: String,
+    pub address: String,
+    pub data: String,
+    pub topics: Vec<String>,
+}
+
+impl RPCLog {
+    pub fn new(log: &LocalizedLog) -> Self {
+        RPCLog {
+            removed: false,
+            log_index: to_quantity(log.log_index),
+            transaction_index: to_quantity(log.transaction_index),
+            transaction_hash: log.transaction_hash.map(to_hash),
+            block_hash: to_hash(log.block_hash),
+            block_number: to_quantity(log.block_number),
+            address: to_hash(log.log.address),
+            data: to_hex(&log.log.data),
+            topics: log.log.topics.iter().map(to_hash).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RPCReceipt {
+    pub transaction_hash: String,
+    pub transaction_index: String,
+    pub block_hash: String,
+    pub block_number: String,
+    pub cumulative_gas_used: String,
+    pub gas_used: String,
+    pub contract_address: Option<String>,
+    pub logs: Vec<RPCLog>,
+    pub logs_bloom: String,
+    pub root: String,
+}
+
+impl RPCReceipt {
+    /// Build the receipt of the transaction at `index` of a block,
+    /// given all receipts of that block.
+    pub fn new(transaction: &Transaction, header: &Header, index: usize,
+               receipts: &[Receipt]) -> Self {
+        let receipt = &receipts[index];
+        let gas_used = if index == 0 {
+            receipt.used_gas
+        } else {
+            receipt.used_gas - receipts[index - 1].used_gas
+        };
+        let log_offset: usize = receipts[0..index].iter().map(|r| r.logs.len()).sum();
+        let logs_bloom: H2048 = receipt.logs_bloom.clone().into();
+
+        RPCReceipt {
+            transaction_hash: to_hash(transaction.rlp_hash()),
+            transaction_index: to_quantity(index),
+            block_hash: to_hash(header.header_hash()),
+            block_number: to_quantity(header.number),
+            cumulative_gas_used: to_quantity(receipt.used_gas),
+            gas_used: to_quantity(gas_used),
+            contract_address: match transaction.action {
+                TransactionAction::Create => transaction.address().ok().map(to_hash),
+                TransactionAction::Call(_) => None,
+            },
+            logs: receipt.logs.iter().enumerate().map(|(i, log)| RPCLog::new(&LocalizedLog {
+                log: log.clone(),
+                block_hash: header.header_hash(),
+                block_number: header.number,
+                transaction_hash: Some(transaction.rlp_hash()),
+                transaction_index: index,
+                log_index: log_offset + i,
+            })).collect(),
+            logs_bloom: to_hash(logs_bloom),
+            root: to_hash(receipt.state_root),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RPCTransaction {
+    pub hash: String,
+    pub nonce: String,
+    pub block_hash: String,
+    pub block_number: String,
+    pub transaction_index: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub value: String,
+    pub gas_price: String,
+    pub gas: String,
+    pub input: String,
+    pub v: String,
+    pub r: String,
+    pub s: String,
+}
+
+impl RPCTransaction {
+    pub fn new(transaction: &Transaction, header: &Header, index: usize) -> Self {
+        RPCTransaction {
+            hash: to_hash(transaction.rlp_hash()),
+            nonce: to_quantity(transaction.nonce),
+            block_hash: to_hash(header.header_hash()),
+            block_number: to_quantity(header.number),
+            transaction_index: to_quantity(index),
+            from: transaction.caller().ok().map(to_hash),
+            to: match transaction.action {
+                TransactionAction::Call(address) => Some(to_hash(address)),
+                TransactionAction::Create => None,
+            },
+            value: to_quantity(transaction.value),
+            gas_price: to_quantity(transaction.gas_price),
+            gas: to_quantity(transaction.gas_limit),
+            input: to_hex(&transaction.input),
+            v: to_quantity(transaction.signature.v),
+            r: to_hash(transaction.signature.r),
+            s: to_hash(transaction.signature.s),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum RPCTransactions {
+    Hashes(Vec<String>),
+    Full(Vec<RPCTransaction>),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RPCBlock {
+    pub number: String,
+    pub hash: String,
+    pub parent_hash: String,
+    pub nonce: String,
+    pub mix_hash: String,
+    pub sha3_uncles: String,
+    pub logs_bloom: String,
+    pub transactions_root: String,
+    pub state_root: String,
+    pub receipts_root: String,
+    pub miner: String,
+    pub difficulty: String,
+    pub total_difficulty: String,
+    pub extra_data: String,
+    pub size: String,
+    pub gas_limit: String,
+    pub gas_used: String,
+    pub timestamp: String,
+    pub transactions: RPCTransactions,
+    pub uncles: Vec<String>,
+}
+
+impl RPCBlock {
+    pub fn new(block: &Block, total_difficulty: U256, full: bool) -> Self {
+        let header = &block.header;
+        let size = rlp::encode(block).len();
+        let logs_bloom: H2048 = header.logs_bloom.clone().into();
+        let extra_data: Vec<u8> = rlp::decode(&rlp::encode(&header.extra_data));
+
+        RPCBlock {
+            number: to_quantity(header.number),
+            hash: to_hash(header.header_hash()),
+            parent_hash: to_hash(header.parent_hash),
+            nonce: to_hash(header.nonce),
+            mix_hash: to_hash(header.mix_hash),
+            sha3_uncles: to_hash(header.ommers_hash),
+            logs_bloom: to_hash(logs_bloom),
+            transactions_root: to_hash(header.transactions_root),
+            state_root: to_hash(header.state_root),
+            receipts_root: to_hash(header.receipts_root),
+            miner: to_hash(header.beneficiary),
+            difficulty: to_quantity(header.difficulty),
+            total_difficulty: to_quantity(total_difficulty),
+            extra_data: to_hex(&extra_data),
+            size: to_quantity(size),
+            gas_limit: to_quantity(header.gas_limit),
+            gas_used: to_quantity(header.gas_used),
+            timestamp: to_quantity(header.timestamp),
+            transactions: if full {
+                RPCTransactions::Full(block.transactions.iter().enumerate()
+                                      .map(|(i, t)| RPCTransaction::new(t, header, i)).collect())
+            } else {
+                RPCTransactions::Hashes(block.transactions.iter()
+                                        .map(|t| to_hash(t.rlp_hash())).collect())
+            },
+            uncles: block.ommers.iter().map(|o| to_hash(o.header_hash())).collect(),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use bigint::{H256, U256};
+use block::{Header, Transaction, Receipt};
+use blockchain::chain::HeaderHash;
+use db::{ChainDatabase, StateDatabase};
+use rlp;
+
+use std::cmp::min;
+
+/// Most headers returned for a single request.
+pub const MAX_HEADERS: usize = 192;
+/// Most bodies returned for a single request.
+pub const MAX_BODIES: usize = 128;
+/// Most trie nodes and codes returned for a single request.
+pub const MAX_NODE_DATA: usize = 384;
+/// Most blocks' receipts returned for a single request.
+pub const MAX_RECEIPTS: usize = 256;
+/// Once a response grows past this many bytes no more items are
+/// added to it.
+pub const SOFT_RESPONSE_LIMIT: usize = 2 * 1024 * 1024;
+
+fn next_number(number: U256, skip: usize, reverse: bool) -> Option<U256> {
+    let step = U256::from(skip) + U256::one();
+    if reverse {
+        if number >= step { Some(number - step) } else { None }
+    } else {
+        match number.overflowing_add(step) {
+            (next, false) => Some(next),
+            (_, true) => None,
+        }
+    }
+}
+
+/// Canonical headers starting at `number`, each `skip` blocks apart,
+/// walking towards genesis if `reverse` is set.
+pub fn headers_by_number(
+    chain: &ChainDatabase, number: U256, max_headers: usize, skip: usize, reverse: bool
+) -> Vec<Header> {
+    let max_headers = min(max_headers, MAX_HEADERS);
+    let mut ret = Vec::new();
+    let mut size = 0;
+    let mut current = Some(number);
+
+    while let Some(number) = current {
+        if ret.len() >= max_headers || size >= SOFT_RESPONSE_LIMIT {
+            break;
+        }
+        let header = match chain.canonical_hash(number).and_then(|hash| chain.header(hash)) {
+            Some(header) => header,
+            None => break,
+        };
+
+        size += rlp::encode(&header).len();
+        ret.push(header);
+        current = next_number(number, skip, reverse);
+    }
+
+    ret
+}
+
+/// Headers starting at the block `hash`. If that block is not
+/// canonical, only its own header is returned.
+pub fn headers_by_hash(
+    chain: &ChainDatabase, hash: H256, max_headers: usize, skip: usize, reverse: bool
+) -> Vec<Header> {
+    let header = match chain.header(hash) {
+        Some(header) => header,
+        None => return Vec::new(),
+    };
+
+    if chain.canonical_hash(header.number) == Some(header.header_hash()) {
+        headers_by_number(chain, header.number, max_headers, skip, reverse)
+    } else if max_headers > 0 {
+        vec![header]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Bodies of the requested blocks, skipping unknown ones.
+pub fn bodies(chain: &ChainDatabase, hashes: &[H256]) -> Vec<(Vec<Transaction>, Vec<Header>)> {
+    let mut ret = Vec::new();
+    let mut size = 0;
+
+    for hash in hashes.iter().take(MAX_BODIES) {
+        if size >= SOFT_RESPONSE_LIMIT {
+            break;
+        }
+        if let Some((transactions, ommers)) = chain.body(*hash) {
+            size += rlp::encode_list(&transactions).len() + rlp::encode_list(&ommers).len();
+            ret.push((transactions, ommers));
+        }
+    }
+
+    ret
+}
+
+/// Trie nodes and contract codes with the requested hashes, skipping
+/// unknown ones.
+pub fn node_data(state: &StateDatabase, hashes: &[H256]) -> Vec<Vec<u8>> {
+    let mut ret = Vec::new();
+    let mut size = 0;
+
+    for hash in hashes.iter().take(MAX_NODE_DATA) {
+        if size >= SOFT_RESPONSE_LIMIT {
+            break;
+        }
+        if let Some(value) = state.node(*hash) {
+            size += value.len();
+            ret.push(value);
+        }
+    }
+
+    ret
+}
+
+/// Receipts of the requested blocks, skipping blocks whose receipts
+/// are not stored.
+pub fn receipts(chain: &ChainDatabase, hashes: &[H256]) -> Vec<Vec<Receipt>> {
+    let mut ret = Vec::new();
+    let mut size = 0;
+
+    for hash in hashes.iter().take(MAX_RECEIPTS) {
+        if size >= SOFT_RESPONSE_LIMIT {
+            break;
+        }
+        if let Some(receipts) = chain.receipts(*hash) {
+            size += rlp::encode_list(&receipts).len();
+            ret.push(receipts);
+        }
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::{KeyValueStore, MemoryKeyValueStore};
+    use validator::EthereumProcessor;
+    use spec::ChainSpec;
+    use std::sync::Arc;
+
+    #[test]
+    fn serve_genesis() {
+        let store: Arc<KeyValueStore> = Arc::new(MemoryKeyValueStore::new());
+        let processor = EthereumProcessor::new(store, ChainSpec::classic());
+        let chain = processor.chain();
+        let genesis = processor.best_header();
+
+        assert_eq!(headers_by_number(chain, U256::zero(), 10, 0, false), vec![genesis.clone()]);
+        assert_eq!(headers_by_number(chain, U256::zero(), 10, 3, true), vec![genesis.clone()]);
+        assert_eq!(headers_by_number(chain, U256::zero(), 0, 0, false), Vec::<Header>::new());
+        assert_eq!(headers_by_hash(chain, genesis.header_hash(), 10, 0, false), vec![genesis.clone()]);
+        assert_eq!(headers_by_hash(chain, H256::default(), 10, 0, false), Vec::<Header>::new());
+        assert_eq!(bodies(chain, &[genesis.header_hash(), H256::default()]),
+                   vec![(Vec::new(), Vec::new())]);
+        assert_eq!(node_data(processor.state(), &[genesis.state_root, H256::default()]).len(), 1);
+    }
+}
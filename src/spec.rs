@@ -0,0 +1,225 @@
+use bigint::{H256, H64, U256, Gas, Address};
+use hexutil::*;
+use serde_json;
+
+use std::str::FromStr;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+/// Hard fork rules a block is validated with. Each variant maps to a
+/// `patch::Patch` type.
+pub enum PatchKind {
+    #[serde(rename = "frontier")]
+    Frontier,
+    #[serde(rename = "homestead")]
+    Homestead,
+    #[serde(rename = "eip150")]
+    EIP150,
+    #[serde(rename = "eip160")]
+    EIP160,
+    #[serde(rename = "ecip1017")]
+    ECIP1017,
+}
+
+#[derive(Clone, Debug)]
+pub struct GenesisSpec {
+    pub parent_hash: H256,
+    pub beneficiary: Address,
+    pub difficulty: U256,
+    pub gas_limit: Gas,
+    pub timestamp: u64,
+    pub extra_data: Vec<u8>,
+    pub nonce: H64,
+    pub mix_hash: H256,
+    pub alloc: Vec<(Address, U256)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A trusted block that header sync can start from instead of
+/// genesis.
+pub struct Checkpoint {
+    pub hash: H256,
+    pub number: U256,
+    pub total_difficulty: U256,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A block peers are challenged for after connecting. Peers whose
+/// block at `number` has a different hash are on the other side of a
+/// contentious fork and get disconnected.
+pub struct ForkCheck {
+    pub number: U256,
+    pub hash: H256,
+}
+
+#[derive(Clone, Debug)]
+/// Description of a chain: its genesis block, network parameters and
+/// the block numbers at which hard forks activate.
+pub struct ChainSpec {
+    pub name: String,
+    pub network_id: usize,
+    pub bootnodes: Vec<String>,
+    pub genesis: GenesisSpec,
+    /// Sorted by block number, starting from zero.
+    pub transitions: Vec<(U256, PatchKind)>,
+    pub checkpoint: Option<Checkpoint>,
+    pub fork_check: Option<ForkCheck>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JSONChainSpec {
+    name: String,
+    network_id: usize,
+    bootnodes: Vec<String>,
+    genesis: JSONGenesisSpec,
+    transitions: Vec<JSONTransition>,
+    #[serde(default)]
+    checkpoint: Option<JSONCheckpoint>,
+    #[serde(default)]
+    fork_check: Option<JSONForkCheck>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JSONGenesisSpec {
+    parent_hash: String,
+    beneficiary: String,
+    difficulty: String,
+    gas_limit: String,
+    timestamp: u64,
+    extra_data: String,
+    nonce: String,
+    mix_hash: String,
+    alloc: HashMap<String, JSONAccount>,
+}
+
+#[derive(Deserialize)]
+struct JSONAccount {
+    balance: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JSONCheckpoint {
+    hash: String,
+    number: u64,
+    total_difficulty: String,
+}
+
+#[derive(Deserialize)]
+struct JSONForkCheck {
+    number: u64,
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct JSONTransition {
+    number: u64,
+    patch: PatchKind,
+}
+
+fn parse<T: FromStr>(field: &str, value: &str) -> Result<T, String> {
+    T::from_str(value).map_err(|_| format!("invalid {}: {}", field, value))
+}
+
+impl ChainSpec {
+    /// The Ethereum Classic mainnet.
+    pub fn classic() -> Self {
+        Self::from_json(include_str!("../res/classic.json")).unwrap()
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let mut content = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| e.to_string())?;
+        Self::from_json(&content)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, String> {
+        let spec: JSONChainSpec = serde_json::from_str(s).map_err(|e| e.to_string())?;
+
+        let mut alloc = Vec::new();
+        for (address, account) in spec.genesis.alloc {
+            alloc.push((parse("alloc address", &address)?,
+                        U256::from_dec_str(&account.balance)
+                        .map_err(|_| format!("invalid alloc balance: {}", account.balance))?));
+        }
+
+        let mut transitions: Vec<(U256, PatchKind)> = spec.transitions.iter()
+            .map(|t| (U256::from(t.number), t.patch)).collect();
+        transitions.sort_by_key(|t| t.0);
+        if transitions.len() == 0 || transitions[0].0 != U256::zero() {
+            return Err("transitions must start at block 0".to_string());
+        }
+
+        let checkpoint = match spec.checkpoint {
+            Some(checkpoint) => Some(Checkpoint {
+                hash: parse("checkpoint hash", &checkpoint.hash)?,
+                number: U256::from(checkpoint.number),
+                total_difficulty: parse("checkpoint totalDifficulty", &checkpoint.total_difficulty)?,
+            }),
+            None => None,
+        };
+
+        let fork_check = match spec.fork_check {
+            Some(fork_check) => Some(ForkCheck {
+                number: U256::from(fork_check.number),
+                hash: parse("forkCheck hash", &fork_check.hash)?,
+            }),
+            None => None,
+        };
+
+        Ok(ChainSpec {
+            name: spec.name,
+            network_id: spec.network_id,
+            bootnodes: spec.bootnodes,
+            genesis: GenesisSpec {
+                parent_hash: parse("parentHash", &spec.genesis.parent_hash)?,
+                beneficiary: parse("beneficiary", &spec.genesis.beneficiary)?,
+                difficulty: parse("difficulty", &spec.genesis.difficulty)?,
+                gas_limit: parse("gasLimit", &spec.genesis.gas_limit)?,
+                timestamp: spec.genesis.timestamp,
+                extra_data: read_hex(&spec.genesis.extra_data)
+                    .map_err(|_| format!("invalid extraData: {}", spec.genesis.extra_data))?,
+                nonce: parse("nonce", &spec.genesis.nonce)?,
+                mix_hash: parse("mixHash", &spec.genesis.mix_hash)?,
+                alloc,
+            },
+            transitions,
+            checkpoint,
+            fork_check,
+        })
+    }
+
+    /// The hard fork rules in effect at the given block number.
+    pub fn patch_at(&self, number: U256) -> PatchKind {
+        let mut patch = self.transitions[0].1;
+        for &(start, kind) in &self.transitions {
+            if number >= start {
+                patch = kind;
+            }
+        }
+        patch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classic_transitions() {
+        let spec = ChainSpec::classic();
+
+        assert_eq!(spec.patch_at(U256::zero()), PatchKind::Frontier);
+        assert_eq!(spec.patch_at(U256::from(1149999)), PatchKind::Frontier);
+        assert_eq!(spec.patch_at(U256::from(1150000)), PatchKind::Homestead);
+        assert_eq!(spec.patch_at(U256::from(2500000)), PatchKind::EIP150);
+        assert_eq!(spec.patch_at(U256::from(5000000)), PatchKind::EIP160);
+        assert_eq!(spec.patch_at(U256::from(5000001)), PatchKind::ECIP1017);
+    }
+}
@@ -0,0 +1,1320 @@
+mod peers;
+mod skeleton;
+mod state;
+mod receipts;
+
+pub use self::peers::{PeerManager, Request, ResponseKind};
+pub use self::skeleton::{Skeleton, FillError};
+pub use self::state::StateSync;
+pub use self::receipts::ReceiptSync;
+
+use bigint::{H256, H512, U256};
+use block::{Header, Block, Transaction, Receipt, RlpHash, transactions_root, ommers_hash};
+use blockchain::chain::HeaderHash;
+use p2p::{ETHSendMessage, ETHReceiveMessage, ETHMessage};
+use devp2p::rlpx::RLPxNode;
+use hexutil::read_hex;
+use rlp;
+use validator::{EthereumProcessor, ImportResult};
+use serve;
+use spec::Checkpoint;
+use txpool::{TransactionPool, PoolError};
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Instant, Duration};
+
+/// Most headers requested from a peer at once.
+const REQUEST_MAX_HEADERS: usize = 192;
+/// How many blocks behind the head to re-request headers from, so
+/// that competing branches are noticed once sync has caught up.
+const FORK_LOOKBACK: usize = 64;
+/// How long a peer has to answer a request.
+const REQUEST_TIMEOUT: u64 = 20;
+/// Most bodies requested from a single peer at once.
+const BODY_BATCH: usize = 64;
+/// No more headers are requested while this many downloaded headers
+/// are waiting for validation.
+const MAX_HEADER_BACKLOG: usize = 16 * skeleton::SKELETON_SPACING;
+/// Most state entries requested from a peer at once.
+const NODE_DATA_BATCH: usize = 384;
+/// Most blocks whose receipts are requested from a peer at once.
+const RECEIPT_BATCH: usize = 64;
+/// How many blocks behind the network's head the state is downloaded
+/// at, so that the pivot is unlikely to be reorganized away.
+const PIVOT_DISTANCE: usize = 64;
+/// First eth version with `GetNodeData` and `GetReceipts`.
+const ETH63: usize = 63;
+
+const ETC_DAO_BLOCK: &str = "f903cff9020fa0a218e2c611f21232d857e3c8cecdcdf1f65f25a4477f98f6f47e4063807f2308a01dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d493479461c808d82a3ac53231750dadc13c777b59310bd9a0614d7d358b03cbdaf0343529673be20ad45809d02487f023e047efdce9da8affa0d33068a7f21bff5018a00ca08a3566a06be4196dfe9e39f96e431565a619d455a07bda9aa65977800376129148cbfe89d35a016dd51c95d6e6dc1e76307d315468b90100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000008638c3bf2616aa831d4c008347e7c08301482084578f7aa78fe4b883e5bda9e7a59ee4bb99e9b1bca0c52daa7054babe515b17ee98540c0889cf5e1595c5dd77496997ca84a68c8da18805276a600980199df901b9f86c018504a817c8008252089453d284357ec70ce289d6d64134dfac8e511c8a3d888b6cfa3afc058000801ba08d94a55c7ac7adbfa2285ef7f4b0c955ae1a02647452cd4ead03ee6f449675c6a067149821b74208176d78fc4dffbe37c8b64eecfd47532406b9727c4ae8eb7c9af86d018504a817c8008252089453d284357ec70ce289d6d64134dfac8e511c8a3d890116db7272d6d94000801ca06d31e3d59bfea97a34103d8ce767a8fe7a79b8e2f30af1e918df53f9e78e69aba0098e5b80e1cc436421aa54eb17e96b08fe80d28a2fbd46451b56f2bca7a321e7f86c018504a817c8008252089453d284357ec70ce289d6d64134dfac8e511c8a3d8814da2c24e0d37014801ba0fdbbc462a8a60ac3d8b13ee236b45af9b7991cf4f0f556d3af46aa5aeca242aba05de5dc03fdcb6cf6d14609dbe6f5ba4300b8ff917c7d190325d9ea2144a7a2fbf86c018504a817c8008252089453d284357ec70ce289d6d64134dfac8e511c8a3d880e301365046d5000801ba0bafb9f71cef873b9e0395b9ed89aac4f2a752e2a4b88ba3c9b6c1fea254eae73a01cef688f6718932f7705d9c1f0dd5a8aad9ddb196b826775f6e5703fdb997706c0";
+
+/// Block synchronisation state machine. It consumes messages received
+/// from the ETH protocol and returns the messages to send in reply,
+/// leaving networking and timers to the caller. `tick` must be called
+/// once `next_tick` has passed, and peers returned by
+/// `take_disconnects` should be disconnected.
+///
+/// With a configured fork check, peers are only used once their
+/// header at the fork block matches, and are banned otherwise.
+///
+/// In light mode with a configured checkpoint, the checkpoint header
+/// is fetched first and sync starts from it instead of genesis.
+///
+/// Far from the network's head, headers are downloaded as a skeleton
+/// of sparse headers whose gaps are filled by different peers in
+/// parallel. Once the skeleton request comes back empty, headers are
+/// followed one batch at a time from the download head. Bodies are
+/// requested in bounded batches from all idle peers.
+///
+/// At the head, new blocks are fetched as soon as peers announce them
+/// rather than by polling, and blocks that become canonical are
+/// announced to the peers that do not know them yet.
+///
+/// In state mode, headers are synced as in light mode until no peer
+/// has newer ones. The state of the block `PIVOT_DISTANCE` below the
+/// head is then downloaded from eth/63 peers, after which that block
+/// becomes the head and sync continues in full mode.
+///
+/// Light mode, and state mode below the pivot, import blocks without
+/// executing them, so their receipts are downloaded from eth/63 peers
+/// that have nothing else to do, and checked against the headers
+/// before being stored.
+pub struct Syncer {
+    processor: EthereumProcessor,
+    mode: SyncMode,
+    /// Transactions received from peers. Only used in full mode, as
+    /// validating them needs the head state.
+    pool: TransactionPool,
+    peers: PeerManager,
+    best_number: U256,
+    best_hash: H256,
+    /// Checkpoint whose header still has to be fetched.
+    checkpoint: Option<Checkpoint>,
+    /// Receipt download of the blocks imported without execution.
+    receipts: Option<ReceiptSync>,
+    /// State download of the pivot, in state mode once it is chosen.
+    state: Option<StateSync>,
+    validated_number: usize,
+    /// Downloaded headers not yet validated, with the peer that sent
+    /// them.
+    known_headers: Vec<(Header, H512)>,
+    known_bodies: HashMap<(H256, H256), (Vec<Transaction>, Vec<Header>, H512)>,
+    requested_bodies: HashSet<H256>,
+    skeleton: Option<Skeleton>,
+    /// Whether a skeleton or head following request is outstanding.
+    head_pending: bool,
+    near_head: bool,
+    /// Whether the last head following request returned nothing new,
+    /// so that the head is only requested again on ticks and
+    /// announcements.
+    at_tip: bool,
+    /// Imported blocks to announce to peers.
+    announcements: Vec<(H256, U256)>,
+    interval: Duration,
+    next_tick: Instant,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Download bodies and execute every block.
+    Full,
+    /// Only download and verify headers, following the canonical
+    /// header chain without any state.
+    Light,
+    /// Download headers, then the state of a recent block, and
+    /// continue in full mode from that block.
+    State,
+}
+
+/// Header of Ethereum Classic's block 1920000, served to peers
+/// checking which side of the DAO fork we are on before we synced
+/// that far.
+fn etc_dao_header() -> Header {
+    let block: Block = rlp::decode(&read_hex(ETC_DAO_BLOCK).unwrap());
+    block.header
+}
+
+fn is_chain(headers: &[Header]) -> bool {
+    headers.windows(2).all(|pair| pair[1].parent_hash == pair[0].header_hash())
+}
+
+impl Syncer {
+    /// Resume syncing from the processor's best block, re-requesting
+    /// every `interval` while waiting for peers.
+    pub fn new(processor: EthereumProcessor, mode: SyncMode, interval: Duration) -> Self {
+        let best_number = processor.best_header().number;
+        let best_hash = processor.best_hash();
+        let checkpoint = match (mode, processor.pending_checkpoint()) {
+            (SyncMode::Light, checkpoint) => checkpoint,
+            (_, Some(_)) => {
+                println!("checkpoints are only used in light mode, syncing from block {}", best_number);
+                None
+            },
+            (_, None) => None,
+        };
+        let state = match mode {
+            SyncMode::State => StateSync::resume(processor.chain(), processor.state().clone()),
+            _ => None,
+        };
+        // A finished state sync, like a full sync, leaves the state of
+        // the head stored.
+        let mode = if mode == SyncMode::State && state.is_none() && best_number > U256::zero() &&
+            processor.state().node(processor.best_header().state_root).is_some()
+        {
+            SyncMode::Full
+        } else {
+            mode
+        };
+        let receipts = match mode {
+            SyncMode::Light => {
+                let start = processor.spec().checkpoint.as_ref()
+                    .map(|checkpoint| checkpoint.number).unwrap_or(U256::one());
+                Some(ReceiptSync::new(processor.chain().clone(), start))
+            },
+            SyncMode::State => Some(ReceiptSync::new(processor.chain().clone(), U256::one())),
+            // Finish the receipts of a state sync.
+            SyncMode::Full => processor.chain().receipt_sync_next()
+                .map(|_| ReceiptSync::new(processor.chain().clone(), U256::one())),
+        };
+
+        Self {
+            processor, mode, best_number, best_hash, checkpoint, receipts, state,
+            pool: TransactionPool::new(),
+            peers: PeerManager::new(Duration::new(REQUEST_TIMEOUT, 0)),
+            validated_number: 0,
+            known_headers: Vec::new(),
+            known_bodies: HashMap::new(),
+            requested_bodies: HashSet::new(),
+            skeleton: None,
+            head_pending: false,
+            near_head: false,
+            at_tip: false,
+            announcements: Vec::new(),
+            interval,
+            next_tick: Instant::now() + interval,
+        }
+    }
+
+    pub fn processor(&self) -> &EthereumProcessor {
+        &self.processor
+    }
+
+    pub fn mode(&self) -> SyncMode {
+        self.mode
+    }
+
+    pub fn pool(&self) -> &TransactionPool {
+        &self.pool
+    }
+
+    pub fn peers(&self) -> &PeerManager {
+        &self.peers
+    }
+
+    /// Number of the last header downloaded, which may be ahead of
+    /// the last validated block.
+    pub fn best_number(&self) -> U256 {
+        self.best_number
+    }
+
+    pub fn active_peers(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn next_tick(&self) -> Instant {
+        self.next_tick
+    }
+
+    /// Peers that misbehaved and should be disconnected.
+    pub fn take_disconnects(&mut self) -> Vec<H512> {
+        self.peers.take_dropped()
+    }
+
+    /// Treat a peer as one of our own nodes: it is not challenged
+    /// with the fork check and never banned.
+    pub fn trust(&mut self, node: H512) {
+        self.peers.trust(node);
+    }
+
+    /// Peers that passed the fork check, or connected if the chain has
+    /// none, since the last call. These are worth reconnecting to after
+    /// a restart.
+    pub fn take_verified(&mut self) -> Vec<H512> {
+        self.peers.take_verified()
+    }
+
+    /// Expire slow requests, send new requests to idle peers and
+    /// validate any blocks whose bodies have arrived.
+    pub fn tick(&mut self, now: Instant) -> Vec<ETHSendMessage> {
+        for (node, request) in self.peers.expire(now) {
+            debug!("request {:?} to peer {} timed out", request, node);
+            if request == Request::ForkCheck {
+                self.peers.kick(node);
+            }
+            self.release(request);
+        }
+
+        self.find_and_validate();
+        let mut ret = self.schedule(now);
+
+        if self.near_head && self.validated_number == self.known_headers.len() {
+            if let Some(node) = self.peers.idle_peer() {
+                let best_number = self.processor.best_header().number.as_usize();
+                self.peers.send(node, Request::Headers { skip: 0 }, now);
+                ret.push(ETHSendMessage {
+                    node: RLPxNode::Peer(node),
+                    data: ETHMessage::GetBlockHeadersByNumber {
+                        number: U256::from(best_number.saturating_sub(FORK_LOOKBACK)),
+                        max_headers: REQUEST_MAX_HEADERS,
+                        skip: 0,
+                        reverse: false,
+                    }
+                });
+            }
+        }
+
+        self.next_tick = now + self.interval;
+        ret
+    }
+
+    pub fn handle(&mut self, message: ETHReceiveMessage, now: Instant) -> Vec<ETHSendMessage> {
+        match message {
+            ETHReceiveMessage::Normal { node, data, .. } => self.handle_normal(node, data, now),
+            ETHReceiveMessage::Connected { node, version } => {
+                if !self.peers.connect(node, version) {
+                    return Vec::new();
+                }
+                // Otherwise the peer is verified once it answers the
+                // fork check sent after its status.
+                let mut ret = Vec::new();
+                if self.processor.spec().fork_check.is_none() || self.peers.is_trusted(node) {
+                    self.peers.verify(node);
+                    ret.extend(self.send_pending(node));
+                }
+                ret.extend(self.schedule(now));
+                ret
+            },
+            ETHReceiveMessage::Disconnected { node } => {
+                if let Some(request) = self.peers.disconnect(node) {
+                    self.release(request);
+                }
+                Vec::new()
+            },
+        }
+    }
+
+    fn handle_normal(&mut self, node: H512, data: ETHMessage, now: Instant) -> Vec<ETHSendMessage> {
+        match data {
+            ETHMessage::Status { network_id, total_difficulty, best_hash, genesis_hash, .. } => {
+                if network_id != self.processor.spec().network_id ||
+                    genesis_hash != self.processor.genesis_hash()
+                {
+                    debug!("peer {} is on network {} with genesis {}", node, network_id, genesis_hash);
+                    self.peers.kick(node);
+                    return Vec::new();
+                }
+                self.peers.update_head(node, best_hash, Some(total_difficulty));
+                self.check_fork(node, now)
+            },
+
+            ETHMessage::NewBlock { block, total_difficulty } =>
+                self.receive_new_block(node, block, total_difficulty, now),
+
+            ETHMessage::NewBlockHashes(hashes) =>
+                self.receive_new_block_hashes(node, hashes, now),
+
+            ETHMessage::Transactions(transactions) => {
+                trace!("received {} new transactions", transactions.len());
+                for transaction in &transactions {
+                    self.peers.mark_transaction(node, transaction.rlp_hash());
+                }
+                let added = transactions.into_iter()
+                    .filter(|transaction| self.add_transaction(transaction.clone()))
+                    .collect();
+                self.propagate(added)
+            },
+
+            ETHMessage::GetBlockHeadersByNumber {
+                number, max_headers, skip, reverse
+            } => {
+                trace!("requested header {}", number);
+                let mut headers = serve::headers_by_number(
+                    self.processor.chain(), number, max_headers, skip, reverse);
+                let fork_check = self.processor.spec().fork_check.clone();
+                if let Some(check) = fork_check {
+                    // Answer fork checks of other peers before the
+                    // fork block is synced.
+                    if headers.len() == 0 && number == check.number {
+                        let dao = etc_dao_header();
+                        if dao.header_hash() == check.hash {
+                            trace!("requested DAO header");
+                            headers.push(dao);
+                        }
+                    }
+                }
+                vec![ETHSendMessage {
+                    node: RLPxNode::Peer(node),
+                    data: ETHMessage::BlockHeaders(headers),
+                }]
+            },
+
+            ETHMessage::GetBlockHeadersByHash {
+                hash, max_headers, skip, reverse
+            } => {
+                trace!("requested header {}", hash);
+                vec![ETHSendMessage {
+                    node: RLPxNode::Peer(node),
+                    data: ETHMessage::BlockHeaders(serve::headers_by_hash(
+                        self.processor.chain(), hash, max_headers, skip, reverse)),
+                }]
+            },
+
+            ETHMessage::GetBlockBodies(hash) => {
+                trace!("requested body {:?}", hash);
+                vec![ETHSendMessage {
+                    node: RLPxNode::Peer(node),
+                    data: ETHMessage::BlockBodies(serve::bodies(self.processor.chain(), &hash)),
+                }]
+            },
+
+            ETHMessage::GetNodeData(hashes) => {
+                trace!("requested {} state entries", hashes.len());
+                vec![ETHSendMessage {
+                    node: RLPxNode::Peer(node),
+                    data: ETHMessage::NodeData(serve::node_data(self.processor.state(), &hashes)),
+                }]
+            },
+
+            ETHMessage::GetReceipts(hashes) => {
+                trace!("requested receipts of {} blocks", hashes.len());
+                vec![ETHSendMessage {
+                    node: RLPxNode::Peer(node),
+                    data: ETHMessage::Receipts(serve::receipts(self.processor.chain(), &hashes)),
+                }]
+            },
+
+            ETHMessage::BlockHeaders(headers) => {
+                trace!("received block headers of len {}", headers.len());
+                let mut ret = Vec::new();
+                match self.peers.receive(node, ResponseKind::Headers) {
+                    Some(Request::Checkpoint) => self.receive_checkpoint(node, headers),
+                    Some(Request::ForkCheck) => {
+                        self.receive_fork_check(node, headers);
+                        if self.peers.is_verified(node) {
+                            ret = self.send_pending(node);
+                        }
+                    },
+                    Some(Request::Headers { skip }) => self.receive_headers(node, skip, headers),
+                    Some(Request::Skeleton { base }) => self.receive_skeleton(node, base, headers),
+                    Some(Request::Fill { base, index }) => self.receive_fill(node, base, index, headers),
+                    _ => {
+                        debug!("ignoring unsolicited headers from {}", node);
+                        return Vec::new();
+                    },
+                }
+                trace!("new best number {}", self.best_number);
+
+                self.find_and_validate();
+                ret.extend(self.schedule(now));
+                ret
+            },
+
+            ETHMessage::BlockBodies(bodies) => {
+                trace!("received block bodies of len {}", bodies.len());
+                match self.peers.receive(node, ResponseKind::Bodies) {
+                    Some(Request::Bodies(hashes)) => self.receive_bodies(node, hashes, bodies),
+                    _ => {
+                        debug!("ignoring unsolicited bodies from {}", node);
+                        return Vec::new();
+                    },
+                }
+
+                self.find_and_validate();
+                self.schedule(now)
+            },
+
+            ETHMessage::NodeData(nodes) => {
+                trace!("received {} state entries", nodes.len());
+                match self.peers.receive(node, ResponseKind::NodeData) {
+                    Some(Request::NodeData(hashes)) => self.receive_node_data(node, hashes, nodes),
+                    _ => {
+                        debug!("ignoring unsolicited state entries from {}", node);
+                        return Vec::new();
+                    },
+                }
+
+                self.schedule(now)
+            },
+
+            ETHMessage::Receipts(receipts) => {
+                trace!("received receipts of {} blocks", receipts.len());
+                match self.peers.receive(node, ResponseKind::Receipts) {
+                    Some(Request::Receipts(_)) => self.receive_receipts(node, receipts),
+                    _ => {
+                        debug!("ignoring unsolicited receipts from {}", node);
+                        return Vec::new();
+                    },
+                }
+
+                self.schedule(now)
+            },
+
+            msg => {
+                trace!("received {:?}", msg);
+                Vec::new()
+            },
+        }
+    }
+
+    /// Pool a transaction submitted locally and send it to all peers.
+    pub fn submit(&mut self, transaction: Transaction) -> Vec<ETHSendMessage> {
+        if self.add_transaction(transaction.clone()) {
+            self.propagate(vec![transaction])
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Add a transaction to the pool, returning whether it is new and
+    /// valid.
+    fn add_transaction(&mut self, transaction: Transaction) -> bool {
+        if self.mode != SyncMode::Full {
+            return false;
+        }
+
+        match self.pool.add(&self.processor, transaction) {
+            Ok(hash) => {
+                debug!("added transaction {} to the pool", hash);
+                true
+            },
+            Err(PoolError::AlreadyKnown) => false,
+            Err(err) => {
+                debug!("rejected transaction: {:?}", err);
+                false
+            },
+        }
+    }
+
+    /// Send the pool's executable transactions to a newly verified
+    /// peer, so that they reach it without waiting for new ones.
+    fn send_pending(&mut self, node: H512) -> Vec<ETHSendMessage> {
+        if self.mode != SyncMode::Full || self.pool.len() == 0 {
+            return Vec::new();
+        }
+
+        let transactions = self.pool.pending(&self.processor);
+        for transaction in &transactions {
+            self.peers.mark_transaction(node, transaction.rlp_hash());
+        }
+        if transactions.is_empty() {
+            return Vec::new();
+        }
+        vec![ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: ETHMessage::Transactions(transactions),
+        }]
+    }
+
+    /// Send transactions to the peers that do not know them yet.
+    fn propagate(&mut self, transactions: Vec<Transaction>) -> Vec<ETHSendMessage> {
+        let mut batches: HashMap<H512, Vec<Transaction>> = HashMap::new();
+        for transaction in transactions {
+            for node in self.peers.unaware_of_transaction(transaction.rlp_hash()) {
+                batches.entry(node).or_insert_with(Vec::new).push(transaction.clone());
+            }
+        }
+
+        batches.into_iter().map(|(node, transactions)| ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: ETHMessage::Transactions(transactions),
+        }).collect()
+    }
+
+    fn receive_new_block(
+        &mut self, node: H512, block: Block, total_difficulty: U256, now: Instant
+    ) -> Vec<ETHSendMessage> {
+        let hash = block.header.header_hash();
+        debug!("received new block {} ({})", block.header.number, hash);
+        self.peers.mark_block(node, hash);
+        self.peers.update_head(node, hash, Some(total_difficulty));
+
+        // Only import announced blocks directly when nothing else is
+        // being downloaded, so the download head stays consistent.
+        let at_head = self.near_head && self.skeleton.is_none() && self.known_headers.is_empty();
+        if !at_head || self.processor.chain().header(hash).is_some() {
+            return self.schedule(now);
+        }
+        if self.processor.chain().header(block.header.parent_hash).is_none() {
+            self.at_tip = false;
+            return self.schedule(now);
+        }
+
+        let number = block.header.number;
+        let result = match self.mode {
+            SyncMode::Light | SyncMode::State => self.processor.put_header(block.header.clone()),
+            SyncMode::Full => self.processor.put(block.clone()),
+        };
+        let mut ret = match result {
+            Ok(ImportResult::Canonical(route)) => {
+                debug!("imported announced block {}", number);
+                if self.mode == SyncMode::Full {
+                    self.pool.update(&self.processor, &route);
+                }
+                self.peers.reward(node);
+                self.best_hash = self.processor.best_hash();
+                self.best_number = self.processor.best_header().number;
+
+                // Without executing the block its body is unchecked,
+                // so only the hash is relayed.
+                let data = match self.mode {
+                    SyncMode::Full => ETHMessage::NewBlock {
+                        block, total_difficulty: self.processor.best_total_difficulty(),
+                    },
+                    SyncMode::Light | SyncMode::State => ETHMessage::NewBlockHashes(vec![(hash, number)]),
+                };
+                self.peers.unaware_of_block(hash).into_iter().map(|peer| ETHSendMessage {
+                    node: RLPxNode::Peer(peer),
+                    data: data.clone(),
+                }).collect()
+            },
+            Ok(_) => Vec::new(),
+            Err(err) => {
+                debug!("announced block {} failed validation: {:?}", number, err);
+                self.peers.penalize(node, peers::INVALID_PENALTY);
+                Vec::new()
+            },
+        };
+        ret.extend(self.schedule(now));
+        ret
+    }
+
+    fn receive_new_block_hashes(&mut self, node: H512, hashes: Vec<(H256, U256)>, now: Instant) -> Vec<ETHSendMessage> {
+        trace!("received {} new block hashes", hashes.len());
+        for &(hash, _) in &hashes {
+            self.peers.mark_block(node, hash);
+        }
+
+        let newest = hashes.iter()
+            .filter(|&&(hash, _)| self.processor.chain().header(hash).is_none())
+            .max_by_key(|&&(_, number)| number)
+            .map(|&(hash, _)| hash);
+        if let Some(hash) = newest {
+            self.peers.update_head(node, hash, None);
+            self.at_tip = false;
+        }
+        self.schedule(now)
+    }
+
+    /// Challenge a peer for its header at the fork check block.
+    fn check_fork(&mut self, node: H512, now: Instant) -> Vec<ETHSendMessage> {
+        let number = match self.processor.spec().fork_check {
+            Some(ref check) => check.number,
+            None => return Vec::new(),
+        };
+        if self.peers.is_verified(node) || !self.peers.is_idle(node) {
+            return Vec::new();
+        }
+
+        self.peers.send(node, Request::ForkCheck, now);
+        vec![ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: ETHMessage::GetBlockHeadersByNumber {
+                number, max_headers: 1, skip: 0, reverse: false,
+            },
+        }]
+    }
+
+    fn receive_fork_check(&mut self, node: H512, headers: Vec<Header>) {
+        let check = match self.processor.spec().fork_check {
+            Some(ref check) => check.clone(),
+            None => return,
+        };
+
+        match headers.into_iter().next() {
+            // The peer has not synced up to the fork yet.
+            None => self.peers.verify(node),
+            Some(ref header) if header.number == check.number && header.header_hash() == check.hash => {
+                self.peers.verify(node);
+                self.peers.reward(node);
+            },
+            Some(header) => {
+                debug!("peer {} has block {} ({}) instead of {}, on the other side of the fork",
+                       node, header.number, header.header_hash(), check.hash);
+                self.peers.ban(node);
+            },
+        }
+    }
+
+    fn receive_checkpoint(&mut self, node: H512, headers: Vec<Header>) {
+        self.head_pending = false;
+        let header = match headers.into_iter().next() {
+            Some(header) => header,
+            None => return,
+        };
+
+        match self.processor.put_checkpoint(header) {
+            Ok(()) => {
+                self.peers.reward(node);
+                self.checkpoint = None;
+                self.best_hash = self.processor.best_hash();
+                self.best_number = self.processor.best_header().number;
+                println!("starting from checkpoint {} at block {}", self.best_hash, self.best_number);
+            },
+            Err(err) => {
+                debug!("invalid checkpoint header from {}: {:?}", node, err);
+                self.peers.penalize(node, peers::INVALID_PENALTY);
+            },
+        }
+    }
+
+    fn receive_headers(&mut self, node: H512, skip: usize, headers: Vec<Header>) {
+        self.head_pending = false;
+        if skip == 0 && !is_chain(&headers) {
+            self.peers.penalize(node, peers::INVALID_PENALTY);
+            return;
+        }
+
+        let full = headers.len() >= REQUEST_MAX_HEADERS;
+        if self.extend_headers(headers, node) > 0 {
+            self.peers.reward(node);
+            self.at_tip = false;
+            if full {
+                // Still far behind, go back to skeleton sync.
+                self.near_head = false;
+            }
+        } else {
+            self.at_tip = true;
+        }
+    }
+
+    fn receive_skeleton(&mut self, node: H512, base: U256, headers: Vec<Header>) {
+        self.head_pending = false;
+        if base != self.best_number || self.skeleton.is_some() {
+            return;
+        }
+
+        if headers.len() == 0 {
+            println!("no skeleton after block {}, following the head", base);
+            self.near_head = true;
+            return;
+        }
+
+        match Skeleton::new(base, self.best_hash, headers, node) {
+            Some(skeleton) => {
+                self.peers.reward(node);
+                self.skeleton = Some(skeleton);
+            },
+            None => self.peers.penalize(node, peers::INVALID_PENALTY),
+        }
+    }
+
+    fn receive_fill(&mut self, node: H512, base: U256, index: usize, headers: Vec<Header>) {
+        let result = match self.skeleton {
+            Some(ref mut skeleton) => if skeleton.base() == base {
+                skeleton.fill(index, headers, node)
+            } else {
+                return;
+            },
+            None => return,
+        };
+
+        match result {
+            Ok(()) => self.peers.reward(node),
+            Err(FillError::Invalid) => self.peers.penalize(node, peers::INVALID_PENALTY),
+            Err(FillError::SkeletonMismatch) => {
+                let skeleton_node = self.skeleton.take().unwrap().node();
+                self.peers.penalize(skeleton_node, peers::INVALID_PENALTY);
+            },
+        }
+
+        let (ready, done) = match self.skeleton {
+            Some(ref mut skeleton) => (skeleton.take_ready(), skeleton.is_done()),
+            None => return,
+        };
+        for (header, node) in ready {
+            if header.parent_hash != self.best_hash {
+                self.skeleton = None;
+                return;
+            }
+            self.best_hash = header.header_hash();
+            self.best_number = header.number;
+            self.known_headers.push((header, node));
+        }
+        if done {
+            self.skeleton = None;
+        }
+    }
+
+    fn receive_bodies(&mut self, node: H512, hashes: Vec<H256>, bodies: Vec<(Vec<Transaction>, Vec<Header>)>) {
+        for hash in &hashes {
+            self.requested_bodies.remove(hash);
+        }
+
+        let hashes: HashSet<H256> = hashes.into_iter().collect();
+        let wanted: HashSet<(H256, H256)> = self.known_headers.iter()
+            .filter(|&&(ref header, _)| hashes.contains(&header.header_hash()))
+            .map(|&(ref header, _)| (header.transactions_root, header.ommers_hash))
+            .collect();
+
+        let mut invalid = false;
+        for body in bodies {
+            let key = (transactions_root(&body.0), ommers_hash(&body.1));
+            if wanted.contains(&key) {
+                self.known_bodies.insert(key, (body.0, body.1, node));
+            } else {
+                invalid = true;
+            }
+        }
+        if invalid {
+            self.peers.penalize(node, peers::INVALID_PENALTY);
+        } else if wanted.len() > 0 {
+            self.peers.reward(node);
+        }
+    }
+
+    fn receive_node_data(&mut self, node: H512, hashes: Vec<H256>, nodes: Vec<Vec<u8>>) {
+        {
+            let state = match self.state {
+                Some(ref mut state) => state,
+                None => return,
+            };
+
+            match state.receive(nodes) {
+                Some(0) => (),
+                Some(_) => self.peers.reward(node),
+                None => {
+                    debug!("unrequested state entries from {}", node);
+                    self.peers.penalize(node, peers::INVALID_PENALTY);
+                },
+            }
+            // Entries left out of the response are requested again.
+            state.release(&hashes);
+        }
+        self.finish_state();
+    }
+
+    /// Once the state of the pivot is complete, make the pivot the
+    /// head and continue from it in full mode.
+    fn finish_state(&mut self) {
+        let pivot = match self.state {
+            Some(ref state) if state.is_done() => state.pivot(),
+            _ => return,
+        };
+        self.state = None;
+
+        if self.processor.set_pivot(pivot).is_none() {
+            println!("state of pivot {} is not stored, choosing another pivot", pivot);
+            self.processor.chain().set_state_pivot(None);
+            return;
+        }
+        self.mode = SyncMode::Full;
+        self.known_headers.clear();
+        self.validated_number = 0;
+        self.skeleton = None;
+        self.near_head = false;
+        self.at_tip = false;
+        self.best_hash = self.processor.best_hash();
+        self.best_number = self.processor.best_header().number;
+        println!("continuing in full mode from pivot block {}", self.best_number);
+    }
+
+    /// Start downloading the state of the canonical block
+    /// `PIVOT_DISTANCE` blocks behind the head.
+    fn choose_pivot(&mut self) {
+        let number = U256::from(self.processor.best_header().number.as_usize().saturating_sub(PIVOT_DISTANCE));
+        let hash = match self.processor.chain().canonical_hash(number) {
+            Some(hash) => hash,
+            None => return,
+        };
+        println!("downloading the state of block {} ({})", number, hash);
+        self.state = StateSync::new(self.processor.chain(), self.processor.state().clone(), hash);
+    }
+
+    fn receive_receipts(&mut self, node: H512, receipts: Vec<Vec<Receipt>>) {
+        let sync = match self.receipts {
+            Some(ref mut sync) => sync,
+            None => return,
+        };
+
+        // Peers without the receipts answer with none.
+        if receipts.len() == 0 {
+            sync.release();
+        } else if sync.receive(receipts) {
+            self.peers.reward(node);
+        } else {
+            debug!("invalid receipts from {}", node);
+            sync.release();
+            self.peers.penalize(node, peers::INVALID_PENALTY);
+        }
+    }
+
+    /// Forget an abandoned request so that it is sent again.
+    fn release(&mut self, request: Request) {
+        match request {
+            Request::ForkCheck => (),
+            Request::Checkpoint | Request::Headers { .. } |
+            Request::Skeleton { .. } => self.head_pending = false,
+            Request::Fill { base, index } => {
+                if let Some(ref mut skeleton) = self.skeleton {
+                    if skeleton.base() == base {
+                        skeleton.release(index);
+                    }
+                }
+            },
+            Request::Bodies(hashes) => {
+                for hash in hashes {
+                    self.requested_bodies.remove(&hash);
+                }
+            },
+            Request::NodeData(hashes) => {
+                if let Some(ref mut state) = self.state {
+                    state.release(&hashes);
+                }
+            },
+            Request::Receipts(_) => {
+                if let Some(ref mut sync) = self.receipts {
+                    sync.release();
+                }
+            },
+        }
+    }
+
+    /// Append headers continuing the download head, returning how
+    /// many were added.
+    fn extend_headers(&mut self, headers: Vec<Header>, node: H512) -> usize {
+        let mut added = 0;
+        for header in headers {
+            if header.parent_hash == self.best_hash {
+                self.best_hash = header.header_hash();
+                self.best_number = header.number;
+                self.known_headers.push((header, node));
+                added += 1;
+            } else if self.validated_number == self.known_headers.len() &&
+                self.processor.chain().header(header.header_hash()).is_none() &&
+                self.processor.chain().header(header.parent_hash).is_some()
+            {
+                println!("following competing branch at block {}", header.number);
+                self.known_headers.clear();
+                self.validated_number = 0;
+                self.skeleton = None;
+                self.best_hash = header.header_hash();
+                self.best_number = header.number;
+                self.known_headers.push((header, node));
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Hand out body batches, header requests, then state and receipt
+    /// requests to idle peers. In state mode, the pivot is chosen once
+    /// following the head finds no newer headers.
+    fn schedule(&mut self, now: Instant) -> Vec<ETHSendMessage> {
+        if self.mode == SyncMode::State && self.state.is_none() && self.near_head && self.at_tip {
+            self.choose_pivot();
+        }
+
+        let mut ret = self.announce();
+        let mut idle = self.peers.idle_peers();
+        idle.reverse();
+
+        ret.extend(self.schedule_blocks(&mut idle, now));
+        ret.extend(self.schedule_state(&mut idle, now));
+        ret.extend(self.schedule_receipts(&idle, now));
+        ret
+    }
+
+    /// Request missing state entries from the idle eth/63 peers.
+    fn schedule_state(&mut self, idle: &mut Vec<H512>, now: Instant) -> Vec<ETHSendMessage> {
+        let mut ret = Vec::new();
+        if self.state.is_none() {
+            return ret;
+        }
+
+        let nodes: Vec<H512> = idle.iter().rev().cloned()
+            .filter(|node| self.peers.version(*node).map(|v| v >= ETH63).unwrap_or(false))
+            .collect();
+        for node in nodes {
+            let batch = match self.state {
+                Some(ref mut state) => state.next_batch(NODE_DATA_BATCH),
+                None => break,
+            };
+            if batch.len() == 0 {
+                break;
+            }
+            idle.retain(|idle_node| *idle_node != node);
+            self.peers.send(node, Request::NodeData(batch.clone()), now);
+            ret.push(ETHSendMessage {
+                node: RLPxNode::Peer(node),
+                data: ETHMessage::GetNodeData(batch),
+            });
+        }
+
+        // Entries already stored are expanded without requests, which
+        // may complete the state.
+        self.finish_state();
+        ret
+    }
+
+    /// Hand out body batches and header requests to idle peers.
+    fn schedule_blocks(&mut self, idle: &mut Vec<H512>, now: Instant) -> Vec<ETHSendMessage> {
+        let mut ret = Vec::new();
+
+        let mut missing = Vec::new();
+        let needs_bodies = &self.known_headers[self.validated_number..];
+        let needs_bodies = if self.mode == SyncMode::Full { needs_bodies } else { &[] };
+        for &(ref header, _) in needs_bodies {
+            let hash = header.header_hash();
+            if !self.requested_bodies.contains(&hash) &&
+                !self.known_bodies.contains_key(&(header.transactions_root, header.ommers_hash))
+            {
+                missing.push(hash);
+            }
+        }
+        for batch in missing.chunks(BODY_BATCH) {
+            let node = match idle.pop() {
+                Some(node) => node,
+                None => break,
+            };
+            for hash in batch {
+                self.requested_bodies.insert(*hash);
+            }
+            self.peers.send(node, Request::Bodies(batch.to_vec()), now);
+            ret.push(ETHSendMessage {
+                node: RLPxNode::Peer(node),
+                data: ETHMessage::GetBlockBodies(batch.to_vec()),
+            });
+        }
+
+        if self.known_headers.len() - self.validated_number >= MAX_HEADER_BACKLOG {
+            return ret;
+        }
+
+        if let Some(ref checkpoint) = self.checkpoint {
+            if !self.head_pending {
+                if let Some(node) = idle.pop() {
+                    self.head_pending = true;
+                    self.peers.send(node, Request::Checkpoint, now);
+                    ret.push(ETHSendMessage {
+                        node: RLPxNode::Peer(node),
+                        data: ETHMessage::GetBlockHeadersByHash {
+                            hash: checkpoint.hash,
+                            max_headers: 1,
+                            skip: 0,
+                            reverse: false,
+                        }
+                    });
+                }
+            }
+            return ret;
+        }
+
+        if let Some(ref mut skeleton) = self.skeleton {
+            while let Some(node) = idle.pop() {
+                let (index, _) = match skeleton.next_gap() {
+                    Some(gap) => gap,
+                    None => break,
+                };
+                self.peers.send(node, Request::Fill { base: skeleton.base(), index }, now);
+                ret.push(ETHSendMessage {
+                    node: RLPxNode::Peer(node),
+                    data: skeleton.fill_request(index),
+                });
+            }
+        } else if self.head_pending || (self.near_head && self.at_tip) {
+            return ret;
+        } else if let Some(node) = idle.pop() {
+            if self.near_head {
+                self.head_pending = true;
+                self.peers.send(node, Request::Headers { skip: 0 }, now);
+                ret.push(ETHSendMessage {
+                    node: RLPxNode::Peer(node),
+                    data: ETHMessage::GetBlockHeadersByHash {
+                        hash: self.best_hash,
+                        max_headers: REQUEST_MAX_HEADERS,
+                        skip: 0,
+                        reverse: false,
+                    }
+                });
+            } else {
+                self.head_pending = true;
+                self.peers.send(node, Request::Skeleton { base: self.best_number }, now);
+                ret.push(ETHSendMessage {
+                    node: RLPxNode::Peer(node),
+                    data: Skeleton::request(self.best_number),
+                });
+            }
+        }
+
+        ret
+    }
+
+    /// Request the next batch of receipts from an eth/63 peer left
+    /// idle by block downloads.
+    fn schedule_receipts(&mut self, idle: &[H512], now: Instant) -> Vec<ETHSendMessage> {
+        let node = match idle.iter().rev().find(|node| self.peers.version(**node).map(|v| v >= ETH63).unwrap_or(false)) {
+            Some(node) => *node,
+            None => return Vec::new(),
+        };
+        let batch = match self.receipts {
+            Some(ref mut sync) => sync.next_batch(RECEIPT_BATCH),
+            None => return Vec::new(),
+        };
+        if batch.len() == 0 {
+            return Vec::new();
+        }
+        self.peers.send(node, Request::Receipts(batch.clone()), now);
+        vec![ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: ETHMessage::GetReceipts(batch),
+        }]
+    }
+
+    /// Announce newly imported blocks to the peers that do not know
+    /// them yet.
+    fn announce(&mut self) -> Vec<ETHSendMessage> {
+        let mut batches: HashMap<H512, Vec<(H256, U256)>> = HashMap::new();
+        for (hash, number) in ::std::mem::replace(&mut self.announcements, Vec::new()) {
+            for node in self.peers.unaware_of_block(hash) {
+                batches.entry(node).or_insert_with(Vec::new).push((hash, number));
+            }
+        }
+
+        batches.into_iter().map(|(node, hashes)| ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: ETHMessage::NewBlockHashes(hashes),
+        }).collect()
+    }
+
+    /// Validate downloaded blocks in order. If one fails, the peers
+    /// that sent its header and body are penalized and the download
+    /// restarts from the best validated block.
+    fn find_and_validate(&mut self) {
+        while self.validated_number < self.known_headers.len() {
+            let (header, header_node) = self.known_headers[self.validated_number].clone();
+            let hash = header.header_hash();
+            let number = header.number;
+            let key = (header.transactions_root, header.ommers_hash);
+
+            let (result, body_node) = match self.mode {
+                SyncMode::Light | SyncMode::State => {
+                    trace!("validating header {:?} ...", header);
+                    (self.processor.put_header(header), None)
+                },
+                SyncMode::Full => {
+                    let (transactions, ommers, body_node) = match self.known_bodies.get(&key) {
+                        Some(body) => body.clone(),
+                        None => {
+                            trace!("block body not yet found: {}", number);
+                            break;
+                        },
+                    };
+                    let block = Block { header, transactions, ommers };
+
+                    trace!("validating block {:?} ...", block);
+                    (self.processor.put(block), Some(body_node))
+                },
+            };
+
+            match result {
+                Ok(ImportResult::Canonical(route)) => {
+                    if route.retracted.len() > 0 {
+                        println!("chain reorganized, retracted {:?}, enacted {:?}",
+                                 route.retracted, route.enacted);
+                    }
+                    if self.mode == SyncMode::Full {
+                        self.pool.update(&self.processor, &route);
+                    }
+                    if self.near_head {
+                        self.peers.mark_block(header_node, hash);
+                        self.announcements.push((hash, number));
+                    }
+                },
+                Ok(ImportResult::SideBranch) => {
+                    debug!("block {} stored on side branch", number);
+                },
+                Ok(_) => (),
+                Err(err) => {
+                    debug!("block {} failed validation: {:?}", number, err);
+                    self.peers.penalize(header_node, peers::INVALID_PENALTY);
+                    if let Some(body_node) = body_node {
+                        self.peers.penalize(body_node, peers::INVALID_PENALTY);
+                    }
+                    self.known_bodies.remove(&key);
+                    self.known_headers.truncate(self.validated_number);
+                    self.skeleton = None;
+                    self.best_hash = self.processor.best_hash();
+                    self.best_number = self.processor.best_header().number;
+                    break;
+                },
+            }
+
+            // Later headers with the same body, such as other empty
+            // blocks, still need it.
+            if self.known_bodies.contains_key(&key) &&
+                !self.known_headers[self.validated_number + 1..].iter()
+                    .any(|&(ref header, _)| (header.transactions_root, header.ommers_hash) == key)
+            {
+                self.known_bodies.remove(&key);
+            }
+            self.validated_number += 1;
+        }
+
+        self.known_headers.drain(..self.validated_number);
+        self.validated_number = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::{KeyValueStore, MemoryKeyValueStore};
+    use spec::ChainSpec;
+    use std::sync::Arc;
+
+    #[test]
+    fn imported_bodies_are_dropped() {
+        let store: Arc<KeyValueStore> = Arc::new(MemoryKeyValueStore::new());
+        let processor = EthereumProcessor::new(store, ChainSpec::classic());
+        let genesis = processor.best_header();
+        let mut syncer = Syncer::new(processor, SyncMode::Full, Duration::new(10, 0));
+        let node = H512::from(1u64);
+
+        // Both headers use the body, which is kept until the second
+        // one is imported.
+        syncer.known_headers = vec![(genesis.clone(), node), (genesis.clone(), node)];
+        syncer.known_bodies.insert((genesis.transactions_root, genesis.ommers_hash), (Vec::new(), Vec::new(), node));
+        syncer.find_and_validate();
+        assert_eq!(syncer.known_headers.len(), 0);
+        assert_eq!(syncer.known_bodies.len(), 0);
+    }
+
+    #[test]
+    fn foreign_status_is_dropped() {
+        let store: Arc<KeyValueStore> = Arc::new(MemoryKeyValueStore::new());
+        let processor = EthereumProcessor::new(store, ChainSpec::classic());
+        let genesis = processor.best_header();
+        let mut syncer = Syncer::new(processor, SyncMode::Full, Duration::new(10, 0));
+        let now = Instant::now();
+
+        let statuses = vec![
+            (H512::from(1u64), 2, genesis.header_hash()),
+            (H512::from(2u64), 1, H256::default()),
+        ];
+        for (node, network_id, genesis_hash) in statuses {
+            syncer.handle(ETHReceiveMessage::Connected { node, version: 63 }, now);
+            assert_eq!(syncer.handle(ETHReceiveMessage::Normal {
+                node, version: 63, data: ETHMessage::Status {
+                    protocol_version: 63, network_id, total_difficulty: genesis.difficulty,
+                    best_hash: genesis.header_hash(), genesis_hash,
+                },
+            }, now), Vec::new());
+            assert_eq!(syncer.take_disconnects(), vec![node]);
+        }
+        assert_eq!(syncer.active_peers(), 0);
+    }
+
+    #[test]
+    fn scripted_peer() {
+        let store: Arc<KeyValueStore> = Arc::new(MemoryKeyValueStore::new());
+        let processor = EthereumProcessor::new(store, ChainSpec::classic());
+        let genesis = processor.best_header();
+        let mut syncer = Syncer::new(processor, SyncMode::Full, Duration::new(10, 0));
+        let node = H512::from(1u64);
+        let now = Instant::now();
+
+        let status = ETHMessage::Status {
+            protocol_version: 63, network_id: 1, total_difficulty: genesis.difficulty,
+            best_hash: genesis.header_hash(), genesis_hash: genesis.header_hash(),
+        };
+        let fork_check = ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: ETHMessage::GetBlockHeadersByNumber {
+                number: U256::from(1920000), max_headers: 1, skip: 0, reverse: false,
+            },
+        };
+
+        // Peers are challenged for the DAO fork block before syncing
+        // from them.
+        assert_eq!(syncer.handle(ETHReceiveMessage::Connected { node, version: 63 }, now), Vec::new());
+        assert_eq!(syncer.active_peers(), 1);
+        assert_eq!(syncer.handle(ETHReceiveMessage::Normal {
+            node, version: 63, data: status.clone(),
+        }, now), vec![fork_check.clone()]);
+        assert_eq!(etc_dao_header().header_hash(), ChainSpec::classic().fork_check.unwrap().hash);
+        let requests = syncer.handle(ETHReceiveMessage::Normal {
+            node, version: 63, data: ETHMessage::BlockHeaders(vec![etc_dao_header()]),
+        }, now);
+        assert_eq!(requests, vec![ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: Skeleton::request(U256::zero()),
+        }]);
+
+        let reply = syncer.handle(ETHReceiveMessage::Normal {
+            node, version: 63,
+            data: ETHMessage::GetBlockHeadersByNumber {
+                number: U256::zero(), max_headers: 1, skip: 0, reverse: false,
+            },
+        }, now);
+        assert_eq!(reply, vec![ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: ETHMessage::BlockHeaders(vec![genesis.clone()]),
+        }]);
+
+        // An empty skeleton means the peer is not ahead by much, so
+        // the head is followed directly.
+        let requests = syncer.handle(ETHReceiveMessage::Normal {
+            node, version: 63, data: ETHMessage::BlockHeaders(Vec::new()),
+        }, now);
+        assert_eq!(requests, vec![ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: ETHMessage::GetBlockHeadersByHash {
+                hash: genesis.header_hash(), max_headers: REQUEST_MAX_HEADERS, skip: 0, reverse: false,
+            },
+        }]);
+
+        // Nothing new at the head: wait for an announcement instead of
+        // polling again.
+        assert_eq!(syncer.handle(ETHReceiveMessage::Normal {
+            node, version: 63, data: ETHMessage::BlockHeaders(vec![genesis.clone()]),
+        }, now), Vec::new());
+        let announced = H256::from(5u64);
+        let requests = syncer.handle(ETHReceiveMessage::Normal {
+            node, version: 63, data: ETHMessage::NewBlockHashes(vec![(announced, U256::one())]),
+        }, now);
+        assert_eq!(requests, vec![ETHSendMessage {
+            node: RLPxNode::Peer(node),
+            data: ETHMessage::GetBlockHeadersByHash {
+                hash: genesis.header_hash(), max_headers: REQUEST_MAX_HEADERS, skip: 0, reverse: false,
+            },
+        }]);
+        assert_eq!(syncer.peers().head(node), Some((announced, genesis.difficulty)));
+
+        // Unsolicited bodies are ignored, headers that do not form a
+        // chain are penalized.
+        assert_eq!(syncer.handle(ETHReceiveMessage::Normal {
+            node, version: 63, data: ETHMessage::BlockBodies(Vec::new()),
+        }, now), Vec::new());
+        syncer.handle(ETHReceiveMessage::Normal {
+            node, version: 63, data: ETHMessage::BlockHeaders(vec![genesis.clone(), genesis.clone()]),
+        }, now);
+        // The passed fork check earned one point.
+        assert_eq!(syncer.peers().score(node), Some(peers::USEFUL_REWARD - peers::INVALID_PENALTY));
+
+        syncer.tick(now);
+        assert_eq!(syncer.next_tick(), now + Duration::new(10, 0));
+
+        syncer.handle(ETHReceiveMessage::Disconnected { node }, now);
+        assert_eq!(syncer.active_peers(), 0);
+
+        // A peer with a different block at the fork is banned.
+        let other = H512::from(2u64);
+        syncer.handle(ETHReceiveMessage::Connected { node: other, version: 63 }, now);
+        syncer.handle(ETHReceiveMessage::Normal { node: other, version: 63, data: status }, now);
+        let mut eth_header = etc_dao_header();
+        eth_header.timestamp += 1;
+        syncer.handle(ETHReceiveMessage::Normal {
+            node: other, version: 63, data: ETHMessage::BlockHeaders(vec![eth_header]),
+        }, now);
+        assert_eq!(syncer.take_disconnects(), vec![other]);
+    }
+}
@@ -0,0 +1,393 @@
+use bigint::{H256, H512, U256};
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Instant, Duration};
+
+/// Score a newly connected peer starts with.
+const INITIAL_SCORE: i32 = 0;
+/// Highest score a peer can build up.
+const MAX_SCORE: i32 = 100;
+/// Peers at or below this score are disconnected and banned.
+const BAN_SCORE: i32 = -20;
+
+/// Score gained for a response that made progress.
+pub const USEFUL_REWARD: i32 = 1;
+/// Score lost when a request times out.
+pub const TIMEOUT_PENALTY: i32 = 5;
+/// Score lost for a response that does not match the request or
+/// contains invalid data.
+pub const INVALID_PENALTY: i32 = 10;
+
+/// Most transaction hashes remembered per peer.
+const MAX_KNOWN_TRANSACTIONS: usize = 4096;
+/// Most block hashes remembered per peer.
+const MAX_KNOWN_BLOCKS: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A request sent to a peer. eth carries no request ids, so at
+/// most one request is outstanding per peer and responses are matched
+/// by peer and message kind.
+pub enum Request {
+    /// The header of the configured checkpoint.
+    Checkpoint,
+    /// The header at the configured fork check block.
+    ForkCheck,
+    /// Headers following the download head.
+    Headers { skip: usize },
+    /// Skeleton headers following the block `base`.
+    Skeleton { base: U256 },
+    /// Headers filling a gap of the skeleton following `base`.
+    Fill { base: U256, index: usize },
+    Bodies(Vec<H256>),
+    /// State entries of the pivot block.
+    NodeData(Vec<H256>),
+    /// Receipts of blocks imported without execution.
+    Receipts(Vec<H256>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseKind {
+    Headers,
+    Bodies,
+    NodeData,
+    Receipts,
+}
+
+impl Request {
+    pub fn kind(&self) -> ResponseKind {
+        match self {
+            &Request::Checkpoint | &Request::ForkCheck | &Request::Headers { .. } |
+            &Request::Skeleton { .. } | &Request::Fill { .. } => ResponseKind::Headers,
+            &Request::Bodies(_) => ResponseKind::Bodies,
+            &Request::NodeData(_) => ResponseKind::NodeData,
+            &Request::Receipts(_) => ResponseKind::Receipts,
+        }
+    }
+}
+
+struct Peer {
+    /// eth version the peer connected with.
+    version: usize,
+    score: i32,
+    /// Whether the peer passed the fork check. Only verified peers
+    /// are handed sync requests.
+    verified: bool,
+    pending: Option<(Request, Instant)>,
+    /// Latest block the peer announced and the highest total
+    /// difficulty it claimed.
+    head: Option<H256>,
+    total_difficulty: U256,
+    /// Transactions the peer sent or was sent.
+    known_transactions: HashSet<H256>,
+    /// Blocks the peer announced or was announced.
+    known_blocks: HashSet<H256>,
+}
+
+/// Insert into a bounded set of known hashes, returning whether the
+/// hash is new.
+fn remember(known: &mut HashSet<H256>, hash: H256, max: usize) -> bool {
+    if known.len() >= max {
+        known.clear();
+    }
+    known.insert(hash)
+}
+
+/// Tracks connected peers, their outstanding requests and their
+/// scores.
+pub struct PeerManager {
+    peers: HashMap<H512, Peer>,
+    banned: HashSet<H512>,
+    trusted: HashSet<H512>,
+    dropped: Vec<H512>,
+    verified: Vec<H512>,
+    timeout: Duration,
+}
+
+impl PeerManager {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            peers: HashMap::new(),
+            banned: HashSet::new(),
+            trusted: HashSet::new(),
+            dropped: Vec::new(),
+            verified: Vec::new(),
+            timeout,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn score(&self, node: H512) -> Option<i32> {
+        self.peers.get(&node).map(|peer| peer.score)
+    }
+
+    /// Never ban the peer, however it behaves.
+    pub fn trust(&mut self, node: H512) {
+        self.trusted.insert(node);
+    }
+
+    pub fn is_trusted(&self, node: H512) -> bool {
+        self.trusted.contains(&node)
+    }
+
+    /// Register a new connection. Banned peers are not accepted and
+    /// queued for disconnection instead.
+    pub fn connect(&mut self, node: H512, version: usize) -> bool {
+        if self.banned.contains(&node) {
+            self.dropped.push(node);
+            return false;
+        }
+
+        self.peers.insert(node, Peer {
+            version,
+            score: INITIAL_SCORE,
+            verified: false,
+            pending: None,
+            head: None,
+            total_difficulty: U256::zero(),
+            known_transactions: HashSet::new(),
+            known_blocks: HashSet::new(),
+        });
+        true
+    }
+
+    /// Forget a peer, returning its outstanding request.
+    pub fn disconnect(&mut self, node: H512) -> Option<Request> {
+        self.peers.remove(&node).and_then(|peer| peer.pending).map(|(request, _)| request)
+    }
+
+    pub fn version(&self, node: H512) -> Option<usize> {
+        self.peers.get(&node).map(|peer| peer.version)
+    }
+
+    pub fn verify(&mut self, node: H512) {
+        if let Some(peer) = self.peers.get_mut(&node) {
+            if !peer.verified {
+                peer.verified = true;
+                self.verified.push(node);
+            }
+        }
+    }
+
+    pub fn is_verified(&self, node: H512) -> bool {
+        self.peers.get(&node).map(|peer| peer.verified).unwrap_or(false)
+    }
+
+    /// The highest scored verified peer without an outstanding
+    /// request.
+    pub fn idle_peer(&self) -> Option<H512> {
+        self.peers.iter()
+            .filter(|&(_, peer)| peer.verified && peer.pending.is_none())
+            .max_by_key(|&(_, peer)| peer.score)
+            .map(|(node, _)| *node)
+    }
+
+    /// All verified peers without an outstanding request, highest
+    /// scored first.
+    pub fn idle_peers(&self) -> Vec<H512> {
+        let mut idle: Vec<(H512, i32)> = self.peers.iter()
+            .filter(|&(_, peer)| peer.verified && peer.pending.is_none())
+            .map(|(node, peer)| (*node, peer.score))
+            .collect();
+        idle.sort_by(|a, b| b.1.cmp(&a.1));
+        idle.into_iter().map(|(node, _)| node).collect()
+    }
+
+    pub fn send(&mut self, node: H512, request: Request, now: Instant) {
+        if let Some(peer) = self.peers.get_mut(&node) {
+            peer.pending = Some((request, now));
+        }
+    }
+
+    /// Take the outstanding request a response of the given kind from
+    /// the peer answers. Returns `None` for unsolicited responses.
+    pub fn receive(&mut self, node: H512, kind: ResponseKind) -> Option<Request> {
+        let peer = match self.peers.get_mut(&node) {
+            Some(peer) => peer,
+            None => return None,
+        };
+
+        match peer.pending.take() {
+            Some((request, _)) if request.kind() == kind => Some(request),
+            other => {
+                peer.pending = other;
+                None
+            },
+        }
+    }
+
+    pub fn is_idle(&self, node: H512) -> bool {
+        self.peers.get(&node).map(|peer| peer.pending.is_none()).unwrap_or(false)
+    }
+
+    /// Record a block the peer announced as its head, with its total
+    /// difficulty if known.
+    pub fn update_head(&mut self, node: H512, hash: H256, total_difficulty: Option<U256>) {
+        if let Some(peer) = self.peers.get_mut(&node) {
+            peer.head = Some(hash);
+            if let Some(total_difficulty) = total_difficulty {
+                peer.total_difficulty = ::std::cmp::max(peer.total_difficulty, total_difficulty);
+            }
+        }
+    }
+
+    pub fn head(&self, node: H512) -> Option<(H256, U256)> {
+        self.peers.get(&node).and_then(|peer| peer.head.map(|head| (head, peer.total_difficulty)))
+    }
+
+    /// Remember that the peer knows the transaction, so that it is
+    /// not sent to it.
+    pub fn mark_transaction(&mut self, node: H512, hash: H256) {
+        if let Some(peer) = self.peers.get_mut(&node) {
+            remember(&mut peer.known_transactions, hash, MAX_KNOWN_TRANSACTIONS);
+        }
+    }
+
+    /// Peers that do not know the transaction yet, marking it as
+    /// known to them.
+    pub fn unaware_of_transaction(&mut self, hash: H256) -> Vec<H512> {
+        self.peers.iter_mut()
+            .filter(|&(_, ref mut peer)| remember(&mut peer.known_transactions, hash, MAX_KNOWN_TRANSACTIONS))
+            .map(|(node, _)| *node)
+            .collect()
+    }
+
+    /// Remember that the peer knows the block, so that it is not
+    /// announced to it.
+    pub fn mark_block(&mut self, node: H512, hash: H256) {
+        if let Some(peer) = self.peers.get_mut(&node) {
+            remember(&mut peer.known_blocks, hash, MAX_KNOWN_BLOCKS);
+        }
+    }
+
+    /// Peers that do not know the block yet, marking it as known to
+    /// them.
+    pub fn unaware_of_block(&mut self, hash: H256) -> Vec<H512> {
+        self.peers.iter_mut()
+            .filter(|&(_, ref mut peer)| remember(&mut peer.known_blocks, hash, MAX_KNOWN_BLOCKS))
+            .map(|(node, _)| *node)
+            .collect()
+    }
+
+    pub fn reward(&mut self, node: H512) {
+        if let Some(peer) = self.peers.get_mut(&node) {
+            peer.score = ::std::cmp::min(peer.score + USEFUL_REWARD, MAX_SCORE);
+        }
+    }
+
+    /// Lower the peer's score, disconnecting and banning it once the
+    /// score falls too low.
+    pub fn penalize(&mut self, node: H512, penalty: i32) {
+        let ban = match self.peers.get_mut(&node) {
+            Some(peer) => {
+                peer.score -= penalty;
+                peer.score <= BAN_SCORE
+            },
+            None => false,
+        };
+
+        if ban {
+            self.ban(node);
+        }
+    }
+
+    /// Disconnect the peer without banning it.
+    pub fn kick(&mut self, node: H512) {
+        self.peers.remove(&node);
+        self.dropped.push(node);
+    }
+
+    /// Disconnect the peer and refuse it from now on. Trusted peers
+    /// only get their score reset.
+    pub fn ban(&mut self, node: H512) {
+        if self.trusted.contains(&node) {
+            debug!("not banning trusted peer {}", node);
+            if let Some(peer) = self.peers.get_mut(&node) {
+                peer.score = INITIAL_SCORE;
+            }
+            return;
+        }
+
+        debug!("banning peer {}", node);
+        self.peers.remove(&node);
+        self.banned.insert(node);
+        self.dropped.push(node);
+    }
+
+    /// Penalize peers whose outstanding request is older than the
+    /// timeout, returning the requests that were abandoned.
+    pub fn expire(&mut self, now: Instant) -> Vec<(H512, Request)> {
+        let mut expired = Vec::new();
+        for (node, peer) in self.peers.iter_mut() {
+            let timed_out = match peer.pending {
+                Some((_, sent)) => now >= sent + self.timeout,
+                None => false,
+            };
+            if timed_out {
+                expired.push((*node, peer.pending.take().unwrap().0));
+            }
+        }
+
+        for &(node, _) in &expired {
+            self.penalize(node, TIMEOUT_PENALTY);
+        }
+        expired
+    }
+
+    /// Peers that should be disconnected since the last call.
+    pub fn take_dropped(&mut self) -> Vec<H512> {
+        ::std::mem::replace(&mut self.dropped, Vec::new())
+    }
+
+    /// Peers verified since the last call.
+    pub fn take_verified(&mut self) -> Vec<H512> {
+        ::std::mem::replace(&mut self.verified, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_and_ban() {
+        let mut peers = PeerManager::new(Duration::new(5, 0));
+        let node = H512::from(1u64);
+        let now = Instant::now();
+
+        assert!(peers.connect(node, 63));
+        assert_eq!(peers.idle_peer(), None);
+        peers.verify(node);
+        assert_eq!(peers.idle_peer(), Some(node));
+
+        peers.send(node, Request::Headers { skip: 0 }, now);
+        assert_eq!(peers.idle_peer(), None);
+        assert_eq!(peers.receive(node, ResponseKind::Bodies), None);
+        assert_eq!(peers.receive(node, ResponseKind::Headers), Some(Request::Headers { skip: 0 }));
+        peers.reward(node);
+        assert_eq!(peers.score(node), Some(1));
+
+        peers.send(node, Request::Bodies(Vec::new()), now);
+        assert_eq!(peers.expire(now + Duration::new(1, 0)).len(), 0);
+        assert_eq!(peers.expire(now + Duration::new(5, 0)).len(), 1);
+        assert_eq!(peers.score(node), Some(1 - TIMEOUT_PENALTY));
+
+        peers.penalize(node, INVALID_PENALTY);
+        peers.penalize(node, INVALID_PENALTY);
+        assert_eq!(peers.take_dropped(), vec![node]);
+        assert_eq!(peers.len(), 0);
+
+        assert!(!peers.connect(node, 63));
+        assert_eq!(peers.take_dropped(), vec![node]);
+
+        let trusted = H512::from(2u64);
+        peers.trust(trusted);
+        assert!(peers.connect(trusted, 63));
+        peers.penalize(trusted, INVALID_PENALTY);
+        peers.penalize(trusted, INVALID_PENALTY);
+        assert_eq!(peers.take_dropped(), Vec::new());
+        assert_eq!(peers.score(trusted), Some(INITIAL_SCORE));
+    }
+}
@@ -0,0 +1,191 @@
+use bigint::{H256, U256};
+use block::{Header, Receipt, receipts_root};
+use bloom::LogsBloom;
+use blockchain::chain::HeaderHash;
+use db::ChainDatabase;
+
+/// Whether `receipts` are the receipts of the block with `header`:
+/// they must hash to its receipts root, and their blooms must add up
+/// to its logs bloom.
+pub fn verify_receipts(header: &Header, receipts: &[Receipt]) -> bool {
+    let bloom = receipts.iter()
+        .fold(LogsBloom::new(), |bloom, receipt| bloom | receipt.logs_bloom.clone());
+    receipts_root(receipts) == header.receipts_root && bloom == header.logs_bloom
+}
+
+/// Downloads the receipts of canonical blocks that were imported
+/// without executing them, so that log queries can be answered
+/// without re-executing history.
+///
+/// Blocks are walked in ascending order from a persisted position, one
+/// batch at a time. Blocks without transactions get their empty
+/// receipts stored directly, and blocks whose receipts are already
+/// stored are skipped. Receipts are only stored once they verify
+/// against their headers. If the canonical chain was reorganized
+/// below the position, the walk steps back to the first canonical
+/// block without receipts.
+///
+/// `Syncer` sends the requests returned by `next_batch` to eth/63
+/// peers.
+pub struct ReceiptSync {
+    chain: ChainDatabase,
+    start: U256,
+    next: U256,
+    requested: Vec<Header>,
+}
+
+impl ReceiptSync {
+    /// Resume an interrupted receipt download, or start one at block
+    /// `start`, the first block imported without execution.
+    pub fn new(chain: ChainDatabase, start: U256) -> Self {
+        let next = chain.receipt_sync_next().unwrap_or(start);
+        ReceiptSync {
+            chain, start, next,
+            requested: Vec::new(),
+        }
+    }
+
+    /// Number of the first canonical block whose receipts are not
+    /// stored.
+    pub fn next(&self) -> U256 {
+        self.next
+    }
+
+    /// Whether a batch is requested and not yet received.
+    pub fn is_requesting(&self) -> bool {
+        self.requested.len() > 0
+    }
+
+    /// Hashes of up to `max` blocks to request receipts for. Empty if
+    /// a batch is already requested or all canonical blocks have
+    /// their receipts.
+    pub fn next_batch(&mut self, max: usize) -> Vec<H256> {
+        if self.is_requesting() {
+            return Vec::new();
+        }
+
+        let from = self.next;
+        while self.next > self.start {
+            match self.chain.canonical_hash(self.next - U256::one()) {
+                Some(hash) if self.chain.receipts(hash).is_none() => self.next = self.next - U256::one(),
+                _ => break,
+            }
+        }
+
+        let empty_root = receipts_root(&[]);
+        let mut number = self.next;
+        while self.requested.len() < max {
+            let header = match self.chain.canonical_hash(number).and_then(|hash| self.chain.header(hash)) {
+                Some(header) => header,
+                None => break,
+            };
+            let hash = header.header_hash();
+            let stored = self.chain.receipts(hash).is_some();
+            if header.receipts_root == empty_root || stored {
+                if !stored {
+                    self.chain.put_receipts(hash, &[]);
+                }
+                if self.requested.len() == 0 {
+                    self.next = number + U256::one();
+                }
+            } else {
+                self.requested.push(header);
+            }
+            number = number + U256::one();
+        }
+
+        if self.requested.len() == 0 && self.next != from {
+            self.chain.set_receipt_sync_next(self.next);
+        }
+        self.requested.iter().map(|header| header.header_hash()).collect()
+    }
+
+    /// Request the current batch again after its request was
+    /// abandoned.
+    pub fn release(&mut self) {
+        self.requested.clear();
+    }
+
+    /// Store the receipts of the requested batch, in request order.
+    /// Peers may answer with the receipts of only the first blocks, and
+    /// the rest are requested again in the next batch. Returns `false`
+    /// without storing anything if they do not match the requested
+    /// blocks, leaving the batch requested.
+    pub fn receive(&mut self, receipts: Vec<Vec<Receipt>>) -> bool {
+        if receipts.len() == 0 || receipts.len() > self.requested.len() ||
+            !self.requested.iter().zip(receipts.iter()).all(|(header, receipts)| verify_receipts(header, receipts))
+        {
+            return false;
+        }
+
+        for (header, receipts) in self.requested.iter().zip(receipts.iter()) {
+            self.chain.put_receipts(header.header_hash(), receipts);
+        }
+        self.next = self.requested[receipts.len() - 1].number + U256::one();
+        self.chain.set_receipt_sync_next(self.next);
+        self.requested.clear();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigint::{Address, Gas};
+    use block::Log;
+    use db::MemoryKeyValueStore;
+    use testing::header;
+    use std::sync::Arc;
+
+    fn child(parent_hash: H256, number: usize, receipts: &[Receipt]) -> Header {
+        let mut header = header(parent_hash, U256::from(number));
+        header.receipts_root = receipts_root(receipts);
+        header.logs_bloom = receipts.iter().fold(LogsBloom::new(), |bloom, receipt| bloom | receipt.logs_bloom.clone());
+        header
+    }
+
+    #[test]
+    fn download_and_resume() {
+        let address = Address::from(1u64);
+        let mut logs_bloom = LogsBloom::new();
+        logs_bloom.set(&address);
+        let receipts = vec![Receipt {
+            state_root: H256::default(),
+            used_gas: Gas::from(21000u64),
+            logs_bloom,
+            logs: vec![Log { address, topics: Vec::new(), data: Vec::new() }],
+        }];
+
+        let chain = ChainDatabase::new(Arc::new(MemoryKeyValueStore::new()));
+        let mut parent_hash = H256::default();
+        let mut headers = Vec::new();
+        for number in 1..4 {
+            let header = child(parent_hash, number, if number == 2 { &[][..] } else { &receipts[..] });
+            parent_hash = header.header_hash();
+            chain.put_header(&header, U256::from(number));
+            chain.set_canonical_hash(header.number, parent_hash);
+            headers.push(header);
+        }
+
+        let mut sync = ReceiptSync::new(chain.clone(), U256::one());
+        let batch = sync.next_batch(1);
+        assert_eq!(batch, vec![headers[0].header_hash()]);
+        assert_eq!(sync.next_batch(1), Vec::new());
+
+        // Receipts that do not match the header are rejected.
+        let mut forged = receipts.clone();
+        forged[0].logs_bloom = LogsBloom::new();
+        assert!(!sync.receive(vec![forged]));
+        assert!(sync.receive(vec![receipts.clone()]));
+        assert_eq!(chain.receipts(headers[0].header_hash()), Some(receipts.clone()));
+
+        // Block 2 has no transactions, so block 3 is next.
+        let mut sync = ReceiptSync::new(chain.clone(), U256::one());
+        assert_eq!(sync.next_batch(16), vec![headers[2].header_hash()]);
+        assert_eq!(chain.receipts(headers[1].header_hash()), Some(Vec::new()));
+        assert!(!sync.receive(Vec::new()));
+        assert!(sync.receive(vec![receipts.clone()]));
+        assert_eq!(sync.next(), U256::from(4u64));
+        assert_eq!(sync.next_batch(16), Vec::new());
+    }
+}
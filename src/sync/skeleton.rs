@@ -0,0 +1,204 @@
+use bigint::{H256, H512, U256};
+use block::Header;
+use blockchain::chain::HeaderHash;
+use p2p::ETHMessage;
+
+use std::collections::{HashMap, HashSet};
+
+use super::is_chain;
+
+/// Distance between two skeleton headers, and so the number of
+/// headers needed to fill one gap.
+pub const SKELETON_SPACING: usize = 192;
+/// Number of skeleton headers requested at once.
+pub const SKELETON_SIZE: usize = 64;
+/// How many peers may fail to fill the same gap before the skeleton
+/// itself is assumed to be wrong.
+const MAX_FILL_FAILURES: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FillError {
+    /// The response does not fill the gap.
+    Invalid,
+    /// Several peers failed to fill the same gap, so the skeleton
+    /// should be dropped.
+    SkeletonMismatch,
+}
+
+/// Sparse headers every `SKELETON_SPACING` blocks after `base`,
+/// whose gaps are filled independently, possibly by different peers,
+/// and handed out in order once complete.
+pub struct Skeleton {
+    base: U256,
+    base_hash: H256,
+    points: Vec<Header>,
+    node: H512,
+    fills: HashMap<usize, (Vec<Header>, H512)>,
+    pending: HashSet<usize>,
+    failures: HashMap<usize, usize>,
+    next: usize,
+}
+
+impl Skeleton {
+    /// Request for the skeleton following the block `base`.
+    pub fn request(base: U256) -> ETHMessage {
+        ETHMessage::GetBlockHeadersByNumber {
+            number: base + U256::from(SKELETON_SPACING),
+            max_headers: SKELETON_SIZE,
+            skip: SKELETON_SPACING - 1,
+            reverse: false,
+        }
+    }
+
+    /// Build a skeleton from the response of `node`, returning `None`
+    /// if the headers are not spaced as requested.
+    pub fn new(base: U256, base_hash: H256, points: Vec<Header>, node: H512) -> Option<Self> {
+        if points.len() == 0 || points.len() > SKELETON_SIZE {
+            return None;
+        }
+        for (i, point) in points.iter().enumerate() {
+            if point.number != base + U256::from((i + 1) * SKELETON_SPACING) {
+                return None;
+            }
+        }
+
+        Some(Skeleton {
+            base, base_hash, points, node,
+            fills: HashMap::new(),
+            pending: HashSet::new(),
+            failures: HashMap::new(),
+            next: 0,
+        })
+    }
+
+    pub fn base(&self) -> U256 {
+        self.base
+    }
+
+    /// Peer the skeleton headers came from.
+    pub fn node(&self) -> H512 {
+        self.node
+    }
+
+    /// Whether every gap has been filled and handed out.
+    pub fn is_done(&self) -> bool {
+        self.next == self.points.len()
+    }
+
+    /// Pick a gap that is neither filled nor being fetched, marking it
+    /// as being fetched. Returns its index and the number of its first
+    /// header.
+    pub fn next_gap(&mut self) -> Option<(usize, U256)> {
+        for index in self.next..self.points.len() {
+            if !self.fills.contains_key(&index) && !self.pending.contains(&index) {
+                self.pending.insert(index);
+                return Some((index, self.base + U256::from(index * SKELETON_SPACING + 1)));
+            }
+        }
+        None
+    }
+
+    /// Request to fill the gap at `index`.
+    pub fn fill_request(&self, index: usize) -> ETHMessage {
+        ETHMessage::GetBlockHeadersByNumber {
+            number: self.base + U256::from(index * SKELETON_SPACING + 1),
+            max_headers: SKELETON_SPACING,
+            skip: 0,
+            reverse: false,
+        }
+    }
+
+    /// Make the gap at `index` available again after its request was
+    /// abandoned.
+    pub fn release(&mut self, index: usize) {
+        self.pending.remove(&index);
+    }
+
+    pub fn fill(&mut self, index: usize, headers: Vec<Header>, node: H512) -> Result<(), FillError> {
+        self.pending.remove(&index);
+        if index < self.next || index >= self.points.len() || self.fills.contains_key(&index) {
+            return Err(FillError::Invalid);
+        }
+
+        let first_number = self.base + U256::from(index * SKELETON_SPACING + 1);
+        if headers.len() != SKELETON_SPACING || headers[0].number != first_number ||
+            !is_chain(&headers)
+        {
+            return Err(FillError::Invalid);
+        }
+
+        let parent_hash = if index == 0 {
+            self.base_hash
+        } else {
+            self.points[index - 1].header_hash()
+        };
+        let last_hash = headers[headers.len() - 1].header_hash();
+        if headers[0].parent_hash != parent_hash || last_hash != self.points[index].header_hash() {
+            let failures = {
+                let failures = self.failures.entry(index).or_insert(0);
+                *failures += 1;
+                *failures
+            };
+            return if failures >= MAX_FILL_FAILURES {
+                Err(FillError::SkeletonMismatch)
+            } else {
+                Err(FillError::Invalid)
+            };
+        }
+
+        self.fills.insert(index, (headers, node));
+        Ok(())
+    }
+
+    /// Take the headers of all complete gaps directly following the
+    /// ones already taken, with the peer each came from.
+    pub fn take_ready(&mut self) -> Vec<(Header, H512)> {
+        let mut ret = Vec::new();
+        while let Some((headers, node)) = self.fills.remove(&self.next) {
+            ret.extend(headers.into_iter().map(|header| (header, node)));
+            self.next += 1;
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::header;
+
+    #[test]
+    fn fill_gaps_out_of_order() {
+        let base_hash = H256::from(1u64);
+        let mut chain = Vec::new();
+        let mut parent_hash = base_hash;
+        for number in 1..(2 * SKELETON_SPACING + 1) {
+            let header = header(parent_hash, U256::from(number));
+            parent_hash = header.header_hash();
+            chain.push(header);
+        }
+        let points = vec![chain[SKELETON_SPACING - 1].clone(), chain[2 * SKELETON_SPACING - 1].clone()];
+        let node = H512::from(1u64);
+
+        assert!(Skeleton::new(U256::zero(), base_hash, vec![chain[0].clone()], node).is_none());
+        let mut skeleton = Skeleton::new(U256::zero(), base_hash, points, node).unwrap();
+
+        assert_eq!(skeleton.next_gap(), Some((0, U256::one())));
+        assert_eq!(skeleton.next_gap(), Some((1, U256::from(SKELETON_SPACING + 1))));
+        assert_eq!(skeleton.next_gap(), None);
+
+        assert_eq!(skeleton.fill(1, chain[0..SKELETON_SPACING].to_vec(), node), Err(FillError::Invalid));
+        assert_eq!(skeleton.fill(1, chain[SKELETON_SPACING..].to_vec(), node), Ok(()));
+        assert_eq!(skeleton.take_ready().len(), 0);
+
+        assert_eq!(skeleton.next_gap(), None);
+        skeleton.release(0);
+        assert_eq!(skeleton.next_gap(), Some((0, U256::one())));
+        assert_eq!(skeleton.fill(0, chain[0..SKELETON_SPACING].to_vec(), node), Ok(()));
+
+        let ready = skeleton.take_ready();
+        assert_eq!(ready.len(), 2 * SKELETON_SPACING);
+        assert_eq!(ready[0].0, chain[0]);
+        assert!(skeleton.is_done());
+    }
+}
@@ -0,0 +1,230 @@
+use bigint::H256;
+use block::Account;
+use db::{ChainDatabase, StateDatabase};
+use proof::{keccak, node_references};
+use rlp::UntrustedRlp;
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NodeKind {
+    Account,
+    Storage,
+    Code,
+}
+
+/// Downloads the state of a pivot block node by node, so that full
+/// validation can continue from the pivot without executing every
+/// block before it.
+///
+/// Nodes are requested by hash and only stored once their data hashes
+/// to a requested node. The pivot is persisted, and nodes already
+/// stored are expanded locally instead of being requested again, so
+/// an interrupted sync resumes where it stopped.
+///
+/// The trie is walked depth first, so the queue of missing nodes only
+/// holds the siblings along the paths being downloaded rather than a
+/// whole level of the trie, which for the mainnet state would be
+/// millions of hashes.
+///
+/// In state mode, `Syncer` requests the batches returned by
+/// `next_batch` from eth/63 peers with `GetNodeData`.
+pub struct StateSync {
+    database: StateDatabase,
+    pivot: H256,
+    /// Missing nodes, the most recently referenced at the back.
+    queue: Vec<(NodeKind, H256)>,
+    requested: HashMap<H256, NodeKind>,
+    downloaded: usize,
+}
+
+impl StateSync {
+    /// Start downloading the state of the stored block `pivot`.
+    /// Returns `None` if the block is unknown.
+    pub fn new(chain: &ChainDatabase, database: StateDatabase, pivot: H256) -> Option<Self> {
+        let state_root = match chain.header(pivot) {
+            Some(header) => header.state_root,
+            None => return None,
+        };
+        chain.set_state_pivot(Some(pivot));
+
+        let mut queue = Vec::new();
+        if state_root != keccak(&[0x80]) {
+            queue.push((NodeKind::Account, state_root));
+        }
+
+        Some(StateSync {
+            database, pivot, queue,
+            requested: HashMap::new(),
+            downloaded: 0,
+        })
+    }
+
+    /// Resume an interrupted state sync, if there is one.
+    pub fn resume(chain: &ChainDatabase, database: StateDatabase) -> Option<Self> {
+        match chain.state_pivot() {
+            Some(pivot) => Self::new(chain, database, pivot),
+            None => None,
+        }
+    }
+
+    pub fn pivot(&self) -> H256 {
+        self.pivot
+    }
+
+    /// Number of nodes stored so far in this session.
+    pub fn downloaded(&self) -> usize {
+        self.downloaded
+    }
+
+    /// Whether the whole state of the pivot is stored.
+    pub fn is_done(&self) -> bool {
+        self.queue.is_empty() && self.requested.is_empty()
+    }
+
+    /// Up to `max` hashes of missing nodes to request, marking them as
+    /// requested.
+    pub fn next_batch(&mut self, max: usize) -> Vec<H256> {
+        let mut ret = Vec::new();
+        while ret.len() < max {
+            let (kind, hash) = match self.queue.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+            if self.requested.contains_key(&hash) {
+                continue;
+            }
+
+            match self.database.node(hash) {
+                Some(data) => self.expand(kind, &data),
+                None => {
+                    self.requested.insert(hash, kind);
+                    ret.push(hash);
+                },
+            }
+        }
+        ret
+    }
+
+    /// Request the given nodes again after their request was
+    /// abandoned.
+    pub fn release(&mut self, hashes: &[H256]) {
+        for hash in hashes {
+            if let Some(kind) = self.requested.remove(hash) {
+                self.queue.push((kind, *hash));
+            }
+        }
+    }
+
+    /// Store the nodes of a response. Returns how many were requested,
+    /// or `None` if the response contains data that was not.
+    pub fn receive(&mut self, nodes: Vec<Vec<u8>>) -> Option<usize> {
+        let mut accepted = 0;
+        let mut invalid = false;
+
+        for data in nodes {
+            let hash = keccak(&data);
+            match self.requested.remove(&hash) {
+                Some(kind) => {
+                    self.database.put_node(hash, &data);
+                    self.expand(kind, &data);
+                    self.downloaded += 1;
+                    accepted += 1;
+                },
+                None => invalid = true,
+            }
+        }
+
+        if self.is_done() {
+            debug!("state of pivot {} downloaded", self.pivot);
+        }
+        if invalid { None } else { Some(accepted) }
+    }
+
+    /// Queue the nodes referenced by a stored node.
+    fn expand(&mut self, kind: NodeKind, data: &[u8]) {
+        if kind == NodeKind::Code {
+            return;
+        }
+
+        let (hashes, values) = match node_references(data) {
+            Some(references) => references,
+            None => {
+                debug!("malformed state node {}", keccak(data));
+                return;
+            },
+        };
+        for hash in hashes {
+            self.queue.push((kind, hash));
+        }
+
+        if kind == NodeKind::Account {
+            for value in values {
+                let account: Account = match UntrustedRlp::new(value).as_val() {
+                    Ok(account) => account,
+                    Err(_) => continue,
+                };
+                if account.storage_root != keccak(&[0x80]) {
+                    self.queue.push((NodeKind::Storage, account.storage_root));
+                }
+                if account.code_hash != keccak(&[]) {
+                    self.queue.push((NodeKind::Code, account.code_hash));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::{KeyValueStore, MemoryKeyValueStore};
+    use validator::EthereumProcessor;
+    use spec::ChainSpec;
+    use trie::Database;
+    use std::sync::Arc;
+
+    #[test]
+    fn download_and_resume() {
+        let source = EthereumProcessor::new(Arc::new(MemoryKeyValueStore::new()), ChainSpec::classic());
+        let genesis = source.best_header();
+
+        let store: Arc<KeyValueStore> = Arc::new(MemoryKeyValueStore::new());
+        let chain = ChainDatabase::new(store.clone());
+        let database = StateDatabase::new(store);
+        chain.put_header(&genesis, genesis.difficulty);
+
+        let mut sync = StateSync::new(&chain, database.clone(), genesis.header_hash()).unwrap();
+        let batch = sync.next_batch(16);
+        assert_eq!(batch, vec![genesis.state_root]);
+        assert_eq!(sync.receive(vec![vec![0x80]]), None);
+
+        // Interrupt after the first node and resume from the store.
+        sync.receive(vec![source.state().node(genesis.state_root).unwrap()]);
+        drop(sync);
+        let mut sync = StateSync::resume(&chain, database.clone()).unwrap();
+        let mut batch = sync.next_batch(16);
+        assert!(batch.len() > 0 && !batch.contains(&genesis.state_root));
+
+        // Walking depth first, the queue never holds a whole level of
+        // the genesis trie, whose third level has thousands of nodes.
+        let mut max_queued = 0;
+        while batch.len() > 0 {
+            let nodes = batch.iter().map(|hash| source.state().node(*hash).unwrap()).collect();
+            assert_eq!(sync.receive(nodes), Some(batch.len()));
+            max_queued = max_queued.max(sync.queue.len());
+            batch = sync.next_batch(16);
+        }
+        assert!(max_queued < 1024);
+        assert!(sync.is_done());
+        assert_eq!(chain.state_pivot(), Some(genesis.header_hash()));
+
+        let (address, _) = ChainSpec::classic().genesis.alloc[0];
+        let expected: Option<Account> = source.state()
+            .create_fixed_secure_trie(genesis.state_root).get(&address);
+        let synced: Option<Account> = database
+            .create_fixed_secure_trie(genesis.state_root).get(&address);
+        assert!(expected.is_some());
+        assert_eq!(synced, expected);
+    }
+}
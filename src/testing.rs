@@ -0,0 +1,35 @@
+//! Header fixtures shared by the unit tests.
+
+use bigint::{Address, Gas, H256, H64, B256, U256};
+use block::Header;
+use blockchain::chain::HeaderHash;
+use bloom::LogsBloom;
+
+/// Empty header with difficulty one.
+pub fn header(parent_hash: H256, number: U256) -> Header {
+    Header {
+        parent_hash,
+        ommers_hash: H256::default(),
+        beneficiary: Address::default(),
+        state_root: H256::default(),
+        transactions_root: H256::default(),
+        receipts_root: H256::default(),
+        logs_bloom: LogsBloom::default(),
+        difficulty: U256::one(),
+        number,
+        gas_limit: Gas::zero(),
+        gas_used: Gas::zero(),
+        timestamp: 0,
+        extra_data: B256::default(),
+        mix_hash: H256::default(),
+        nonce: H64::default(),
+    }
+}
+
+/// Child of `parent`. Siblings with different `beneficiary` values
+/// have different hashes.
+pub fn child(parent: &Header, beneficiary: u64) -> Header {
+    let mut header = header(parent.header_hash(), parent.number + U256::one());
+    header.beneficiary = Address::from(beneficiary);
+    header
+}
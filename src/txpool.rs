@@ -0,0 +1,328 @@
+use bigint::{H256, U256, Gas, Address};
+use block::{Header, Transaction, Account, RlpHash};
+use sputnikvm::ValidTransaction;
+use sputnikvm::errors::PreExecutionError;
+use sputnikvm_stateful::Stateful;
+use db::{ChainRoute, StateDatabase};
+use spec::{ChainSpec, PatchKind};
+use validator::EthereumProcessor;
+use patch::*;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// Most transactions kept in the pool.
+pub const MAX_TRANSACTIONS: usize = 4096;
+/// Most transactions kept for a single sender.
+pub const MAX_PER_SENDER: usize = 64;
+
+#[derive(Debug, Clone)]
+/// Reason a transaction was not added to the pool.
+pub enum PoolError {
+    AlreadyKnown,
+    InvalidSignature,
+    /// The transaction cannot fit in a block with the head's gas
+    /// limit.
+    GasLimitExceeded { limit: Gas, actual: Gas },
+    /// The sender's account already used the nonce.
+    NonceTooLow { expected: U256, actual: U256 },
+    /// The transaction cannot be executed against the head state.
+    Invalid(PreExecutionError),
+    /// A transaction with the same sender and nonce and at least the
+    /// same gas price is already pooled.
+    Underpriced,
+    /// The sender already has `MAX_PER_SENDER` pooled transactions.
+    SenderLimit,
+    /// The pool is full of transactions paying at least as much.
+    PoolFull,
+}
+
+fn validate_with_patch<P: Patch>(
+    database: &StateDatabase, head: &Header, transaction: &Transaction
+) -> Result<Address, PoolError> {
+    if !transaction.is_basic_valid::<P::Signature, P::TransactionValidation>() {
+        return Err(PoolError::InvalidSignature);
+    }
+    if transaction.gas_limit > head.gas_limit {
+        return Err(PoolError::GasLimitExceeded { limit: head.gas_limit, actual: transaction.gas_limit });
+    }
+    let caller = transaction.caller().map_err(|_| PoolError::InvalidSignature)?;
+
+    let stateful = Stateful::new(database, head.state_root);
+    let account: Option<Account> = stateful.state().get(&caller);
+    let (nonce, balance) = account.map(|a| (a.nonce, a.balance)).unwrap_or((U256::zero(), U256::zero()));
+
+    if transaction.nonce < nonce {
+        return Err(PoolError::NonceTooLow { expected: nonce, actual: transaction.nonce });
+    }
+    // The upfront cost is checked with overflow before anything
+    // multiplies it unchecked.
+    let gas_limit: U256 = transaction.gas_limit.into();
+    let gas_price: U256 = transaction.gas_price.into();
+    let (preclaimed, mul_overflow) = gas_limit.overflowing_mul(gas_price);
+    let (cost, add_overflow) = preclaimed.overflowing_add(transaction.value);
+    if mul_overflow || add_overflow || balance < cost {
+        return Err(PoolError::Invalid(PreExecutionError::InsufficientBalance));
+    }
+    if transaction.nonce == nonce {
+        stateful.to_valid::<P::VM>(transaction.clone()).map_err(PoolError::Invalid)?;
+    } else {
+        // `to_valid` only accepts the sender's next nonce, so queued
+        // transactions get the same checks here.
+        let valid = ValidTransaction {
+            caller: Some(caller),
+            gas_price: transaction.gas_price,
+            gas_limit: transaction.gas_limit,
+            action: transaction.action.clone(),
+            value: transaction.value,
+            input: transaction.input.clone(),
+            nonce: transaction.nonce,
+        };
+        if valid.gas_limit < valid.intrinsic_gas::<P::VM>() {
+            return Err(PoolError::Invalid(PreExecutionError::InsufficientGasLimit));
+        }
+    }
+
+    Ok(caller)
+}
+
+/// Check a transaction for inclusion in the block after `head`,
+/// returning its sender.
+pub fn validate(
+    spec: &ChainSpec, database: &StateDatabase, head: &Header, transaction: &Transaction
+) -> Result<Address, PoolError> {
+    match spec.patch_at(head.number + U256::one()) {
+        PatchKind::Frontier => validate_with_patch::<FrontierPatch>(database, head, transaction),
+        PatchKind::Homestead => validate_with_patch::<HomesteadPatch>(database, head, transaction),
+        PatchKind::EIP150 => validate_with_patch::<EIP150Patch>(database, head, transaction),
+        PatchKind::EIP160 => validate_with_patch::<EIP160Patch>(database, head, transaction),
+        PatchKind::ECIP1017 => validate_with_patch::<ECIP1017Patch>(database, head, transaction),
+    }
+}
+
+/// Transactions waiting to be included in a block, validated against
+/// the head state and kept ordered by nonce per sender.
+pub struct TransactionPool {
+    senders: HashMap<Address, BTreeMap<U256, Transaction>>,
+    hashes: HashMap<H256, (Address, U256)>,
+}
+
+impl TransactionPool {
+    pub fn new() -> Self {
+        TransactionPool {
+            senders: HashMap::new(),
+            hashes: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn get(&self, hash: H256) -> Option<&Transaction> {
+        self.hashes.get(&hash).and_then(|&(sender, nonce)| {
+            self.senders.get(&sender).and_then(|transactions| transactions.get(&nonce))
+        })
+    }
+
+    /// Validate a transaction against the processor's head and add
+    /// it, replacing a pooled transaction with the same sender and
+    /// nonce if it pays a higher gas price.
+    pub fn add(&mut self, processor: &EthereumProcessor, transaction: Transaction) -> Result<H256, PoolError> {
+        let hash = transaction.rlp_hash();
+        if self.hashes.contains_key(&hash) {
+            return Err(PoolError::AlreadyKnown);
+        }
+
+        let head = processor.best_header();
+        let sender = validate(processor.spec(), processor.state(), &head, &transaction)?;
+        let nonce = transaction.nonce;
+
+        let replaced_price = self.senders.get(&sender)
+            .and_then(|transactions| transactions.get(&nonce))
+            .map(|transaction| transaction.gas_price);
+        let sender_len = self.senders.get(&sender).map(|transactions| transactions.len()).unwrap_or(0);
+        match replaced_price {
+            Some(price) => if transaction.gas_price <= price {
+                return Err(PoolError::Underpriced);
+            },
+            None => {
+                if sender_len >= MAX_PER_SENDER {
+                    return Err(PoolError::SenderLimit);
+                }
+                if self.len() >= MAX_TRANSACTIONS && !self.evict_cheaper(transaction.gas_price) {
+                    return Err(PoolError::PoolFull);
+                }
+            },
+        }
+
+        let replaced = self.senders.entry(sender).or_insert_with(BTreeMap::new).insert(nonce, transaction);
+        if let Some(replaced) = replaced {
+            self.hashes.remove(&replaced.rlp_hash());
+        }
+        self.hashes.insert(hash, (sender, nonce));
+        Ok(hash)
+    }
+
+    /// Transactions executable on top of the processor's head, in
+    /// nonce order per sender. Across senders, the transaction paying
+    /// the highest gas price comes first.
+    pub fn pending(&self, processor: &EthereumProcessor) -> Vec<Transaction> {
+        let head = processor.best_header();
+        let stateful = Stateful::new(processor.state(), head.state_root);
+        let state = stateful.state();
+
+        let mut runs = Vec::new();
+        for (sender, transactions) in &self.senders {
+            let account: Option<Account> = state.get(sender);
+            let mut next = account.map(|a| a.nonce).unwrap_or(U256::zero());
+            let mut run = VecDeque::new();
+            for (nonce, transaction) in transactions.range(next..) {
+                if *nonce != next {
+                    break;
+                }
+                run.push_back(transaction.clone());
+                next = next + U256::one();
+            }
+            runs.push(run);
+        }
+
+        let mut ret = Vec::new();
+        loop {
+            let best = runs.iter().enumerate()
+                .filter(|&(_, run)| run.len() > 0)
+                .max_by_key(|&(_, run)| run[0].gas_price)
+                .map(|(index, _)| index);
+            match best {
+                Some(index) => ret.push(runs[index].pop_front().unwrap()),
+                None => break,
+            }
+        }
+        ret
+    }
+
+    /// Bring the pool up to date after the canonical head changed:
+    /// transactions of retracted blocks are added back and those
+    /// whose nonce has been used are dropped.
+    pub fn update(&mut self, processor: &EthereumProcessor, route: &ChainRoute) {
+        for hash in &route.retracted {
+            if let Some((transactions, _)) = processor.chain().body(*hash) {
+                for transaction in transactions {
+                    let _ = self.add(processor, transaction);
+                }
+            }
+        }
+
+        let head = processor.best_header();
+        let stateful = Stateful::new(processor.state(), head.state_root);
+        let state = stateful.state();
+        let senders: Vec<Address> = self.senders.keys().cloned().collect();
+        for sender in senders {
+            let account: Option<Account> = state.get(&sender);
+            let nonce = account.map(|a| a.nonce).unwrap_or(U256::zero());
+            let used: Vec<U256> = self.senders[&sender].range(..nonce).map(|(nonce, _)| *nonce).collect();
+            for nonce in used {
+                self.remove(sender, nonce);
+            }
+        }
+    }
+
+    fn remove(&mut self, sender: Address, nonce: U256) -> Option<Transaction> {
+        let (removed, empty) = match self.senders.get_mut(&sender) {
+            Some(transactions) => (transactions.remove(&nonce), transactions.is_empty()),
+            None => return None,
+        };
+        if empty {
+            self.senders.remove(&sender);
+        }
+        if let Some(ref transaction) = removed {
+            self.hashes.remove(&transaction.rlp_hash());
+        }
+        removed
+    }
+
+    /// Make room by dropping the cheapest transaction that is last in
+    /// its sender's nonce order, if it pays less than `gas_price`.
+    fn evict_cheaper(&mut self, gas_price: Gas) -> bool {
+        let cheapest = self.senders.iter()
+            .filter_map(|(sender, transactions)| {
+                transactions.iter().next_back().map(|(nonce, transaction)| (*sender, *nonce, transaction.gas_price))
+            })
+            .min_by_key(|&(_, _, price)| price);
+
+        match cheapest {
+            Some((sender, nonce, price)) if price < gas_price => {
+                self.remove(sender, nonce);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+impl Default for TransactionPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use block::{FromKey, TransactionAction, UnsignedTransaction};
+    use db::MemoryKeyValueStore;
+    use secp256k1::SECP256K1;
+    use secp256k1::key::SecretKey;
+    use std::sync::Arc;
+
+    fn transaction(key: &SecretKey, nonce: u64, gas_price: u64, value: u64) -> Transaction {
+        transaction_priced(key, nonce, Gas::from(gas_price), value)
+    }
+
+    fn transaction_priced(key: &SecretKey, nonce: u64, gas_price: Gas, value: u64) -> Transaction {
+        UnsignedTransaction {
+            nonce: U256::from(nonce),
+            gas_price: gas_price,
+            gas_limit: Gas::from(21000u64),
+            action: TransactionAction::Call(Address::default()),
+            value: U256::from(value),
+            input: Vec::new(),
+        }.sign_global(key)
+    }
+
+    #[test]
+    fn add_replace_and_order() {
+        let key = SecretKey::from_slice(&SECP256K1, &[1u8; 32]).unwrap();
+        let sender = Address::from_secret_key(&key).unwrap();
+        let mut spec = ChainSpec::classic();
+        spec.genesis.gas_limit = Gas::from(1000000u64);
+        spec.genesis.alloc = vec![(sender, U256::from(1000000u64))];
+        let processor = EthereumProcessor::new(Arc::new(MemoryKeyValueStore::new()), spec);
+        let mut pool = TransactionPool::new();
+
+        let first = pool.add(&processor, transaction(&key, 0, 1, 0)).unwrap();
+        pool.add(&processor, transaction(&key, 2, 1, 0)).unwrap();
+        assert_eq!(pool.pending(&processor).len(), 1);
+
+        match pool.add(&processor, transaction(&key, 0, 1, 1)) {
+            Err(PoolError::Underpriced) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        pool.add(&processor, transaction(&key, 0, 2, 0)).unwrap();
+        assert_eq!(pool.len(), 2);
+        assert!(pool.get(first).is_none());
+
+        match pool.add(&processor, transaction(&key, 1, 1, 1000000)) {
+            Err(PoolError::Invalid(PreExecutionError::InsufficientBalance)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        for nonce in 0..3 {
+            match pool.add(&processor, transaction_priced(&key, nonce, Gas::from(U256::max_value()), 0)) {
+                Err(PoolError::Invalid(PreExecutionError::InsufficientBalance)) => (),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+        pool.add(&processor, transaction(&key, 1, 1, 0)).unwrap();
+        let pending = pool.pending(&processor);
+        assert_eq!(pending.iter().map(|t| t.nonce.as_u64()).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+}
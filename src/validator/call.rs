@@ -0,0 +1,148 @@
+use bigint::{H256, U256, Gas, Address};
+use block::{Header, TransactionAction};
+use blockchain::chain::HeaderHash;
+use sputnikvm::{HeaderParams, VM, VMStatus, SeqTransactionVM, ValidTransaction};
+use sputnikvm_stateful::Stateful;
+use db::{StateDatabase, ChainDatabase};
+use spec::{ChainSpec, PatchKind};
+use patch::*;
+
+#[derive(Clone, Debug)]
+/// A message call or contract creation that is executed without
+/// being signed or committed.
+pub struct CallRequest {
+    /// Caller of the call. If it is `None`, the call is executed as
+    /// a system call.
+    pub caller: Option<Address>,
+    /// Callee address. If it is `None`, the call creates a contract.
+    pub to: Option<Address>,
+    pub value: U256,
+    pub data: Vec<u8>,
+    pub gas_limit: Gas,
+    pub gas_price: Gas,
+}
+
+#[derive(Clone, Debug)]
+pub struct CallResult {
+    pub output: Vec<u8>,
+    pub used_gas: Gas,
+    /// Whether the execution stopped with an error, in which case
+    /// all state changes of the call are discarded.
+    pub reverted: bool,
+}
+
+fn call_with_patch<P: Patch>(
+    stateful: &Stateful<StateDatabase>, header: &Header, most_recent_block_hashes: &[H256],
+    request: &CallRequest
+) -> CallResult {
+    let nonce = match request.caller {
+        Some(caller) => stateful.state().get(&caller).map(|a| a.nonce).unwrap_or(U256::zero()),
+        None => U256::zero(),
+    };
+    let transaction = ValidTransaction {
+        caller: request.caller,
+        gas_price: request.gas_price,
+        gas_limit: request.gas_limit,
+        action: match request.to {
+            Some(address) => TransactionAction::Call(address),
+            None => TransactionAction::Create,
+        },
+        value: request.value,
+        input: request.data.clone(),
+        nonce,
+    };
+
+    if request.gas_limit < transaction.intrinsic_gas::<P::VM>() {
+        return CallResult {
+            output: Vec::new(),
+            used_gas: request.gas_limit,
+            reverted: true,
+        };
+    }
+
+    let vm: SeqTransactionVM<P::VM> = stateful.call(
+        transaction, HeaderParams::from(header), most_recent_block_hashes);
+
+    CallResult {
+        output: vm.out().into(),
+        used_gas: vm.real_used_gas(),
+        reverted: match vm.status() {
+            VMStatus::ExitedOk => false,
+            _ => true,
+        },
+    }
+}
+
+fn estimate_gas_with_patch<P: Patch>(
+    stateful: &Stateful<StateDatabase>, header: &Header, most_recent_block_hashes: &[H256],
+    request: &CallRequest
+) -> Result<Gas, CallResult> {
+    let full = call_with_patch::<P>(stateful, header, most_recent_block_hashes, request);
+    if full.reverted {
+        return Err(full);
+    }
+
+    let mut request = request.clone();
+    let mut lower = Gas::zero();
+    let mut upper = request.gas_limit;
+
+    while lower + Gas::from(1u64) < upper {
+        let mid = (lower + upper) / Gas::from(2u64);
+        request.gas_limit = mid;
+
+        if call_with_patch::<P>(stateful, header, most_recent_block_hashes, &request).reverted {
+            lower = mid;
+        } else {
+            upper = mid;
+        }
+    }
+
+    Ok(upper)
+}
+
+/// Execute the call on top of the state of the given block.
+pub fn call(
+    spec: &ChainSpec, database: &StateDatabase, chain: &ChainDatabase,
+    header: &Header, request: &CallRequest
+) -> CallResult {
+    let most_recent_block_hashes = chain.last_hashes(header.header_hash(), 256);
+    let stateful = Stateful::new(database, header.state_root);
+
+    match spec.patch_at(header.number) {
+        PatchKind::Frontier => call_with_patch::<FrontierPatch>(
+            &stateful, header, &most_recent_block_hashes, request),
+        PatchKind::Homestead => call_with_patch::<HomesteadPatch>(
+            &stateful, header, &most_recent_block_hashes, request),
+        PatchKind::EIP150 => call_with_patch::<EIP150Patch>(
+            &stateful, header, &most_recent_block_hashes, request),
+        PatchKind::EIP160 => call_with_patch::<EIP160Patch>(
+            &stateful, header, &most_recent_block_hashes, request),
+        PatchKind::ECIP1017 => call_with_patch::<ECIP1017Patch>(
+            &stateful, header, &most_recent_block_hashes, request),
+    }
+}
+
+/// Find the smallest gas limit, up to the limit in the request, with
+/// which the call succeeds. If it fails even with the full limit,
+/// the failing result is returned. The block hashes and the state are
+/// looked up once for all steps of the search.
+pub fn estimate_gas(
+    spec: &ChainSpec, database: &StateDatabase, chain: &ChainDatabase,
+    header: &Header, request: &CallRequest
+) -> Result<Gas, CallResult> {
+    let most_recent_block_hashes = chain.last_hashes(header.header_hash(), 256);
+    let stateful = Stateful::new(database, header.state_root);
+
+    match spec.patch_at(header.number) {
+        PatchKind::Frontier => estimate_gas_with_patch::<FrontierPatch>(
+            &stateful, header, &most_recent_block_hashes, request),
+        PatchKind::Homestead => estimate_gas_with_patch::<HomesteadPatch>(
+            &stateful, header, &most_recent_block_hashes, request),
+        PatchKind::EIP150 => estimate_gas_with_patch::<EIP150Patch>(
+            &stateful, header, &most_recent_block_hashes, request),
+        PatchKind::EIP160 => estimate_gas_with_patch::<EIP160Patch>(
+            &stateful, header, &most_recent_block_hashes, request),
+        PatchKind::ECIP1017 => estimate_gas_with_patch::<ECIP1017Patch>(
+            &stateful, header, &most_recent_block_hashes, request),
+    }
+}
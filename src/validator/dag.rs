@@ -0,0 +1,40 @@
+use bigint::U256;
+use ethash::{EthereumPatch, LightDAG};
+
+use std::collections::VecDeque;
+
+/// Epochs whose ethash caches are kept.
+const CACHED_EPOCHS: usize = 2;
+
+/// Ethash caches of the most recently used epochs. Generating a cache
+/// takes seconds, and ommers near the start of an epoch belong to the
+/// previous one, so that one is kept alongside the current epoch.
+pub struct DAGCache {
+    dags: VecDeque<LightDAG<EthereumPatch>>,
+}
+
+impl DAGCache {
+    /// Create a cache holding the DAG for block `number`.
+    pub fn new(number: U256) -> Self {
+        let mut cache = DAGCache { dags: VecDeque::new() };
+        cache.prepare(number);
+        cache
+    }
+
+    /// Generate the DAG for block `number` unless it is cached,
+    /// evicting the least recently generated one.
+    pub fn prepare(&mut self, number: U256) {
+        if self.get(number).is_some() {
+            return;
+        }
+        if self.dags.len() >= CACHED_EPOCHS {
+            self.dags.pop_front();
+        }
+        self.dags.push_back(LightDAG::new(number));
+    }
+
+    /// The DAG for block `number`, if it is cached.
+    pub fn get(&self, number: U256) -> Option<&LightDAG<EthereumPatch>> {
+        self.dags.iter().find(|dag| dag.is_valid_for(number))
+    }
+}
@@ -0,0 +1,56 @@
+use bigint::{H256, U256, Gas};
+use bloom::LogsBloom;
+use sputnikvm::errors::PreExecutionError;
+
+#[derive(Debug, Clone)]
+/// Reason a block failed validation. Mismatches carry the value
+/// found in the block header first and the locally computed one
+/// second.
+pub enum ValidationError {
+    /// The parent block is not in the chain.
+    UnknownParent(H256),
+    /// The block is the genesis block or does not point to its
+    /// parent.
+    InvalidParentHash { expected: H256, actual: H256 },
+    InvalidNumber { expected: U256, actual: U256 },
+    /// The header does not have the hash of the configured checkpoint.
+    CheckpointMismatch { expected: H256, actual: H256 },
+    TransactionsRootMismatch { expected: H256, actual: H256 },
+    OmmersHashMismatch { expected: H256, actual: H256 },
+    /// Transaction at the index has an invalid signature.
+    InvalidSignature { index: usize },
+
+    /// Timestamp is not greater than the parent's.
+    InvalidTimestamp { parent: u64, actual: u64 },
+    InvalidDifficulty { expected: U256, actual: U256 },
+
+    InvalidMixHash { expected: H256, actual: H256 },
+    /// The nonce exceeds the difficulty boundary.
+    InvalidNonce { boundary: U256, actual: U256 },
+
+    InvalidGasLimit { parent: Gas, actual: Gas },
+
+    TooManyOmmers { max: usize, actual: usize },
+    /// Ommer at the index is already included in this block or in
+    /// one of its recent ancestors.
+    DuplicateOmmer { index: usize },
+    /// Ommer at the index is itself an ancestor of the block.
+    OmmerIsAncestor { index: usize },
+    /// Ommer at the index does not have a recent ancestor, other than
+    /// the block's parent, as its parent.
+    UnrelatedOmmer { index: usize },
+    /// Ommer at the index is not a valid header.
+    InvalidOmmer { index: usize, error: Box<ValidationError> },
+    /// No ethash cache is generated for the epoch of the header with
+    /// this number. Only the epochs of the block and of ommers recent
+    /// enough to be valid are generated.
+    MissingDAG { number: U256 },
+
+    /// Transaction at the index cannot be executed against the
+    /// current state.
+    InvalidTransaction { index: usize, error: PreExecutionError },
+    StateRootMismatch { expected: H256, actual: H256 },
+    ReceiptsRootMismatch { expected: H256, actual: H256 },
+    LogsBloomMismatch { expected: LogsBloom, actual: LogsBloom },
+    GasUsedMismatch { expected: Gas, actual: Gas },
+}
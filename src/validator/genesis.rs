@@ -6,51 +6,38 @@ use trie::{DatabaseGuard, DatabaseOwned, MemoryTrie};
 use hexutil::*;
 use rlp;
 use bloom::LogsBloom;
-use serde_json;
+use spec::GenesisSpec;
 use ethash;
 use sha3::{Digest, Keccak256};
 use std::str::FromStr;
 use std::collections::HashMap;
 
-pub fn genesis_header(state_root: H256) -> Header {
-    let extra_data = read_hex("11bbe8db4e347b4e8c937c1c8370e4b5ed33adb3db69cbdb7a38e1e50b1b82fa").unwrap();
+pub fn genesis_header(spec: &GenesisSpec, state_root: H256) -> Header {
     let ommers: Vec<Header> = Vec::new();
 
     let mut header = Header {
-        parent_hash: H256::default(),
+        parent_hash: spec.parent_hash,
         ommers_hash: H256::from(Keccak256::digest(&rlp::encode_list(&ommers).to_vec()).as_slice()),
-        beneficiary: Address::default(),
+        beneficiary: spec.beneficiary,
         state_root,
         transactions_root: MemoryTrie::empty(HashMap::new()).root(),
         receipts_root: MemoryTrie::empty(HashMap::new()).root(),
         logs_bloom: LogsBloom::default(),
-        difficulty: U256::from(0x400000000usize),
+        difficulty: spec.difficulty,
         number: U256::zero(),
-        gas_limit: Gas::from(0x1388usize),
+        gas_limit: spec.gas_limit,
         gas_used: Gas::zero(),
-        timestamp: 0,
-        extra_data: B256::new(&extra_data),
-        nonce: H64::from_str("0x0000000000000042").unwrap(),
-        mix_hash: H256::default(),
+        timestamp: spec.timestamp,
+        extra_data: B256::new(&spec.extra_data),
+        nonce: spec.nonce,
+        mix_hash: spec.mix_hash,
     };
 
     header
 }
 
-pub fn transit_genesis<D: DatabaseOwned>(stateful: &mut Stateful<D>) {
-    #[derive(Serialize, Deserialize, Debug)]
-    struct JSONAccount {
-        balance: String,
-    }
-
-    let genesis_accounts: HashMap<String, JSONAccount> =
-        serde_json::from_str(include_str!("../../res/genesis.json")).unwrap();
-
-    let mut accounts: Vec<(&String, &JSONAccount)> = genesis_accounts.iter().collect();
-    for (key, value) in accounts {
-        let address = Address::from_str(key).unwrap();
-        let balance = U256::from_dec_str(&value.balance).unwrap();
-
+pub fn transit_genesis<D: DatabaseOwned>(spec: &GenesisSpec, stateful: &mut Stateful<D>) {
+    for &(address, balance) in &spec.alloc {
         let vm: SeqTransactionVM<FrontierPatch> = stateful.execute(ValidTransaction {
             caller: None,
             gas_price: Gas::zero(),
@@ -60,11 +47,11 @@ pub fn transit_genesis<D: DatabaseOwned>(stateful: &mut Stateful<D>) {
             input: Vec::new(),
             nonce: U256::zero(),
         }, HeaderParams {
-            beneficiary: Address::default(),
-            timestamp: 0,
+            beneficiary: spec.beneficiary,
+            timestamp: spec.timestamp,
             number: U256::zero(),
-            difficulty: U256::from(0x400000000usize),
-            gas_limit: Gas::from(0x1388usize)
+            difficulty: spec.difficulty,
+            gas_limit: spec.gas_limit,
         }, &[]);
         match vm.status() {
             VMStatus::ExitedOk => (),
@@ -79,15 +66,17 @@ mod tests {
     use trie::MemoryDatabase;
     use ethash;
     use blockchain::chain::HeaderHash;
+    use spec::ChainSpec;
 
     #[test]
     fn mainnet_genesis_block() {
         let database = MemoryDatabase::default();
+        let spec = ChainSpec::classic().genesis;
 
         let mut stateful = MemoryStateful::empty(&database);
-        transit_genesis(&mut stateful);
+        transit_genesis(&spec, &mut stateful);
 
-        let genesis = genesis_header(stateful.root());
+        let genesis = genesis_header(&spec, stateful.root());
         assert_eq!(genesis.header_hash(), H256::from_str("d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3").unwrap());
     }
 }
@@ -1,18 +1,27 @@
 mod genesis;
+mod error;
+mod call;
+mod dag;
+
+pub use self::error::ValidationError;
+pub use self::call::{CallRequest, CallResult, call, estimate_gas};
+pub use self::dag::DAGCache;
 
-use trie::{MemoryDatabase, FixedMemoryTrie};
 use bigint::{U256, H256, H64, Gas};
 use block::{Header, Receipt, TotalHeader, Transaction, Block, Log, TransactionAction, ommers_hash, transactions_root, receipts_root};
 use bloom::LogsBloom;
 use sha3::{Digest, Keccak256};
 use rlp;
 use ethash::{self, cross_boundary, LightDAG};
-use blockchain::chain::{HeaderHash, Chain};
+use blockchain::chain::HeaderHash;
 use sputnikvm::{HeaderParams, VM, SeqTransactionVM, ValidTransaction};
-use sputnikvm_stateful::MemoryStateful;
+use sputnikvm_stateful::Stateful;
+use db::{KeyValueStore, StateDatabase, ChainDatabase, ChainRoute};
+use spec::{ChainSpec, PatchKind, Checkpoint};
 use patch::*;
 
-use std::collections::HashMap;
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
 use std::cmp::{min, max};
 
@@ -24,6 +33,11 @@ pub fn validate_gas_limit(last_gas_limit: Gas, this_gas_limit: Gas) -> bool {
         this_gas_limit >= Gas::from(5000u64)
 }
 
+/// Maximum number of ommers a block may include.
+const MAX_OMMERS: usize = 2;
+/// How many generations back an ommer's parent may be.
+const OMMER_GENERATIONS: usize = 7;
+
 pub fn calculate_difficulty<Base: BaseTargetDifficultyPatch, Bomb: DifficultyBombPatch>(
     last_difficulty: U256, last_timestamp: u64, this_number: U256, this_timestamp: u64
 ) -> U256 {
@@ -40,73 +54,333 @@ pub fn calculate_difficulty<Base: BaseTargetDifficultyPatch, Bomb: DifficultyBom
     target
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Outcome of importing a valid block.
+pub enum ImportResult {
+    /// The block was already imported.
+    AlreadyKnown,
+    /// The block was stored on a side branch with less total
+    /// difficulty than the canonical chain, or one whose ancestors are
+    /// not all stored.
+    SideBranch,
+    /// The block became the new head. If it was on a side branch,
+    /// `retracted` lists the blocks that left the canonical chain.
+    Canonical(ChainRoute),
+}
+
 pub struct EthereumProcessor {
-    database: MemoryDatabase,
-    chain: Chain<H256, TotalHeader, HashMap<H256, TotalHeader>>,
-    dag: LightDAG<ethash::EthereumPatch>,
+    spec: ChainSpec,
+    database: StateDatabase,
+    chain: ChainDatabase,
+    dags: DAGCache,
 }
 
 impl EthereumProcessor {
-    pub fn new() -> Self {
-        let database = MemoryDatabase::default();
+    /// Open a processor on top of the given store. If the store
+    /// already contains a chain, validation resumes from its best
+    /// block, otherwise the genesis block is written first.
+    pub fn new(store: Arc<KeyValueStore>, spec: ChainSpec) -> Self {
+        let database = StateDatabase::new(store.clone());
+        let chain = ChainDatabase::new(store);
+
+        if chain.best_hash().is_none() {
+            let genesis = {
+                let mut stateful = Stateful::empty(&database);
+                genesis::transit_genesis(&spec.genesis, &mut stateful);
+                genesis::genesis_header(&spec.genesis, stateful.root())
+            };
+            let genesis_hash = genesis.header_hash();
 
-        let genesis = {
-            let mut stateful = MemoryStateful::empty(&database);
-            genesis::transit_genesis(&mut stateful);
-            genesis::genesis_header(stateful.root())
-        };
+            chain.put_header(&genesis, genesis.difficulty);
+            chain.put_body(genesis_hash, &[], &[]);
+            chain.put_receipts(genesis_hash, &[]);
+            chain.set_canonical_hash(genesis.number, genesis_hash);
+            chain.set_best_hash(genesis_hash);
+        }
+
+        let best_number = chain.header(chain.best_hash().unwrap()).unwrap().number;
 
         Self {
-            database,
-            chain: Chain::new(TotalHeader::from_genesis(genesis)),
-            dag: LightDAG::new(U256::zero()),
+            spec, database, chain,
+            dags: DAGCache::new(best_number),
         }
     }
 
-    pub fn put(&mut self, block: Block) -> bool {
-        let parent = match self.chain.fetch(block.header.parent_hash) {
-            Some(val) => val.clone(),
-            None => return false,
+    pub fn spec(&self) -> &ChainSpec {
+        &self.spec
+    }
+
+    pub fn chain(&self) -> &ChainDatabase {
+        &self.chain
+    }
+
+    pub fn state(&self) -> &StateDatabase {
+        &self.database
+    }
+
+    pub fn genesis_hash(&self) -> H256 {
+        self.chain.canonical_hash(U256::zero()).unwrap()
+    }
+
+    pub fn best_hash(&self) -> H256 {
+        self.chain.best_hash().unwrap()
+    }
+
+    pub fn best_header(&self) -> Header {
+        self.chain.header(self.best_hash()).unwrap()
+    }
+
+    pub fn best_total_difficulty(&self) -> U256 {
+        self.chain.total_difficulty(self.best_hash()).unwrap()
+    }
+
+    /// Execute a call against the state of the given block without
+    /// committing it. Returns `None` if the block is unknown.
+    pub fn call(&self, request: &CallRequest, block: H256) -> Option<CallResult> {
+        self.chain.header(block).map(|header| {
+            call(&self.spec, &self.database, &self.chain, &header, request)
+        })
+    }
+
+    /// Estimate the gas needed for a call against the state of the
+    /// given block. Returns `None` if the block is unknown.
+    pub fn estimate_gas(&self, request: &CallRequest, block: H256) -> Option<Result<Gas, CallResult>> {
+        self.chain.header(block).map(|header| {
+            estimate_gas(&self.spec, &self.database, &self.chain, &header, request)
+        })
+    }
+
+    /// The configured checkpoint, if the chain has not reached it
+    /// yet.
+    pub fn pending_checkpoint(&self) -> Option<Checkpoint> {
+        match self.spec.checkpoint {
+            Some(ref checkpoint) if self.best_header().number < checkpoint.number =>
+                Some(checkpoint.clone()),
+            _ => None,
+        }
+    }
+
+    /// Store the header of the pending checkpoint as the new head,
+    /// trusting its configured total difficulty. Header validation
+    /// continues from it, and the blocks between genesis and the
+    /// checkpoint are never downloaded.
+    pub fn put_checkpoint(&mut self, header: Header) -> Result<(), ValidationError> {
+        let checkpoint = match self.pending_checkpoint() {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(()),
         };
-        let most_recent_block_hashes = self.chain.last_hashes(256);
-        if !self.dag.is_valid_for(block.header.number) {
-            self.dag = LightDAG::new(block.header.number);
-        }
-
-        {
-            let mut validator: Box<Validator> = if block.header.number < U256::from(1150000) {
-                Box::new(EthereumValidator::<FrontierPatch>::new(
-                    &block, &parent.0, &self.database, &self.dag, &most_recent_block_hashes))
-            } else if block.header.number < U256::from(2500000) {
-                Box::new(EthereumValidator::<HomesteadPatch>::new(
-                    &block, &parent.0, &self.database, &self.dag, &most_recent_block_hashes))
-            } else if block.header.number < U256::from(3000000) {
-                Box::new(EthereumValidator::<EIP150Patch>::new(
-                    &block, &parent.0, &self.database, &self.dag, &most_recent_block_hashes))
-            } else if block.header.number < U256::from(5000001) {
-                Box::new(EthereumValidator::<EIP160Patch>::new(
-                    &block, &parent.0, &self.database, &self.dag, &most_recent_block_hashes))
-            } else {
-                Box::new(EthereumValidator::<ECIP1017Patch>::new(
-                    &block, &parent.0, &self.database, &self.dag, &most_recent_block_hashes))
-            };
+        let hash = header.header_hash();
+        if hash != checkpoint.hash {
+            return Err(ValidationError::CheckpointMismatch { expected: hash, actual: checkpoint.hash });
+        }
+        if header.number != checkpoint.number {
+            return Err(ValidationError::InvalidNumber { expected: header.number, actual: checkpoint.number });
+        }
+
+        self.chain.put_header(&header, checkpoint.total_difficulty);
+        self.chain.set_canonical_hash(header.number, hash);
+        self.chain.set_best_hash(hash);
+        self.dags.prepare(header.number);
+        Ok(())
+    }
 
-            if !validator.validate() {
-                return false;
+    /// Make a stored block whose state was downloaded instead of
+    /// computed the head of the chain, so that full validation
+    /// continues from it. Returns `None` if the block or the root of
+    /// its state is not stored.
+    pub fn set_pivot(&mut self, hash: H256) -> Option<ChainRoute> {
+        let header = match self.chain.header(hash) {
+            Some(header) => header,
+            None => return None,
+        };
+        if self.database.node(header.state_root).is_none() {
+            return None;
+        }
+
+        self.dags.prepare(header.number);
+        let route = self.chain.set_head(hash)?;
+        self.chain.set_state_pivot(None);
+        Some(route)
+    }
+
+    /// Validate and store a block whose parent is already stored. The
+    /// canonical chain switches to the block's branch if its total
+    /// difficulty exceeds that of the current head.
+    pub fn put(&mut self, block: Block) -> Result<ImportResult, ValidationError> {
+        let hash = block.header.header_hash();
+        if self.chain.header(hash).is_some() && self.chain.body(hash).is_some() {
+            return Ok(ImportResult::AlreadyKnown);
+        }
+
+        let (parent, parent_total_difficulty) = match self.chain.fetch(block.header.parent_hash) {
+            Some(val) => val,
+            None => return Err(ValidationError::UnknownParent(block.header.parent_hash)),
+        };
+        let most_recent_block_hashes = self.chain.last_hashes(block.header.parent_hash, 256);
+        self.dags.prepare(block.header.number);
+        for ommer in &block.ommers {
+            // Only ommers this recent can be valid, so older epochs are
+            // not worth generating.
+            if ommer.number < block.header.number &&
+                ommer.number + U256::from(OMMER_GENERATIONS) >= block.header.number
+            {
+                self.dags.prepare(ommer.number);
             }
         }
 
-        self.chain.put(TotalHeader::from_parent(block.header, &parent))
+        let receipts = {
+            let mut validator: Box<Validator> = match self.spec.patch_at(block.header.number) {
+                PatchKind::Frontier => Box::new(EthereumValidator::<FrontierPatch>::new(
+                    &block, &parent, &self.spec, &self.chain, &self.database, &self.dags,
+                    &most_recent_block_hashes)),
+                PatchKind::Homestead => Box::new(EthereumValidator::<HomesteadPatch>::new(
+                    &block, &parent, &self.spec, &self.chain, &self.database, &self.dags,
+                    &most_recent_block_hashes)),
+                PatchKind::EIP150 => Box::new(EthereumValidator::<EIP150Patch>::new(
+                    &block, &parent, &self.spec, &self.chain, &self.database, &self.dags,
+                    &most_recent_block_hashes)),
+                PatchKind::EIP160 => Box::new(EthereumValidator::<EIP160Patch>::new(
+                    &block, &parent, &self.spec, &self.chain, &self.database, &self.dags,
+                    &most_recent_block_hashes)),
+                PatchKind::ECIP1017 => Box::new(EthereumValidator::<ECIP1017Patch>::new(
+                    &block, &parent, &self.spec, &self.chain, &self.database, &self.dags,
+                    &most_recent_block_hashes)),
+            };
+
+            validator.validate()?
+        };
+
+        self.chain.put_body(hash, &block.transactions, &block.ommers);
+        self.chain.put_receipts(hash, &receipts);
+        Ok(self.commit_header(&block.header, parent_total_difficulty + block.header.difficulty))
+    }
+
+    /// Validate and store a header without its body, as done in light
+    /// mode. Only consensus fields are checked and no state is
+    /// computed, so a block imported this way cannot be the parent of
+    /// one imported with `put` unless its state was downloaded and it
+    /// was made the head with `set_pivot`.
+    pub fn put_header(&mut self, header: Header) -> Result<ImportResult, ValidationError> {
+        if self.chain.header(header.header_hash()).is_some() {
+            return Ok(ImportResult::AlreadyKnown);
+        }
+
+        let (parent, parent_total_difficulty) = match self.chain.fetch(header.parent_hash) {
+            Some(val) => val,
+            None => return Err(ValidationError::UnknownParent(header.parent_hash)),
+        };
+        self.dags.prepare(header.number);
+        validate_header_with_spec(&self.spec, &header, &parent, self.dags.get(header.number).unwrap())?;
+
+        Ok(self.commit_header(&header, parent_total_difficulty + header.difficulty))
+    }
+
+    /// Store a validated header, making it the head if it has the most
+    /// total difficulty. A branch that cannot be walked back to the
+    /// canonical chain because some of its headers are not stored is
+    /// kept as a side branch.
+    fn commit_header(&self, header: &Header, total_difficulty: U256) -> ImportResult {
+        self.chain.put_header(header, total_difficulty);
+        if total_difficulty > self.best_total_difficulty() {
+            if let Some(route) = self.chain.set_head(header.header_hash()) {
+                return ImportResult::Canonical(route);
+            }
+        }
+        ImportResult::SideBranch
     }
 }
 
 pub trait Validator {
-    fn validate(&mut self) -> bool;
+    /// Validate the block, returning the receipts of its
+    /// transactions.
+    fn validate(&mut self) -> Result<Vec<Receipt>, ValidationError>;
+}
+
+fn validate_header_timestamp_and_difficulty<P: Patch>(
+    header: &Header, parent: &Header
+) -> Result<(), ValidationError> {
+    if header.timestamp <= parent.timestamp {
+        return Err(ValidationError::InvalidTimestamp {
+            parent: parent.timestamp, actual: header.timestamp,
+        });
+    }
+
+    let difficulty = calculate_difficulty::<P::BaseTargetDifficulty, P::DifficultyBomb>(
+        parent.difficulty, parent.timestamp, header.number, header.timestamp);
+    if header.difficulty != difficulty {
+        return Err(ValidationError::InvalidDifficulty {
+            expected: header.difficulty, actual: difficulty,
+        });
+    }
+
+    Ok(())
+}
+
+fn validate_header_consensus<E: ethash::Patch>(
+    dag: &LightDAG<E>, header: &Header
+) -> Result<(), ValidationError> {
+    let (mix_hash, _) = dag.hashimoto(header.partial_hash(), header.nonce);
+    let nonce_value: u64 = header.nonce.into();
+    let boundary = cross_boundary(header.difficulty);
+
+    if mix_hash != header.mix_hash {
+        return Err(ValidationError::InvalidMixHash {
+            expected: header.mix_hash, actual: mix_hash,
+        });
+    }
+    if U256::from(nonce_value) > boundary {
+        return Err(ValidationError::InvalidNonce {
+            boundary, actual: U256::from(nonce_value),
+        });
+    }
+
+    Ok(())
+}
+
+fn validate_header_gas_limit(header: &Header, parent: &Header) -> Result<(), ValidationError> {
+    if !validate_gas_limit(parent.gas_limit, header.gas_limit) {
+        return Err(ValidationError::InvalidGasLimit {
+            parent: parent.gas_limit, actual: header.gas_limit,
+        });
+    }
+
+    Ok(())
+}
+
+/// Validate the consensus fields of a header against its parent,
+/// without looking at the block body or state.
+pub fn validate_header<P: Patch>(
+    header: &Header, parent: &Header, dag: &LightDAG<P::Ethash>
+) -> Result<(), ValidationError> {
+    if header.number != parent.number + U256::one() {
+        return Err(ValidationError::InvalidNumber {
+            expected: header.number, actual: parent.number + U256::one(),
+        });
+    }
+    validate_header_timestamp_and_difficulty::<P>(header, parent)?;
+    validate_header_gas_limit(header, parent)?;
+    validate_header_consensus(dag, header)
+}
+
+/// Validate the consensus fields of a header with the patch active at
+/// its number.
+fn validate_header_with_spec(
+    spec: &ChainSpec, header: &Header, parent: &Header, dag: &LightDAG<ethash::EthereumPatch>
+) -> Result<(), ValidationError> {
+    match spec.patch_at(header.number) {
+        PatchKind::Frontier => validate_header::<FrontierPatch>(header, parent, dag),
+        PatchKind::Homestead => validate_header::<HomesteadPatch>(header, parent, dag),
+        PatchKind::EIP150 => validate_header::<EIP150Patch>(header, parent, dag),
+        PatchKind::EIP160 => validate_header::<EIP160Patch>(header, parent, dag),
+        PatchKind::ECIP1017 => validate_header::<ECIP1017Patch>(header, parent, dag),
+    }
 }
 
 pub struct EthereumValidator<'a, P: Patch + 'static> {
-    database: &'a MemoryDatabase,
-    dag: &'a LightDAG<P::Ethash>,
+    spec: &'a ChainSpec,
+    chain: &'a ChainDatabase,
+    database: &'a StateDatabase,
+    dags: &'a DAGCache,
     current_block: &'a Block,
     parent_header: &'a Header,
     most_recent_block_hashes: &'a [H256],
@@ -114,84 +388,154 @@ pub struct EthereumValidator<'a, P: Patch + 'static> {
 }
 
 impl<'a, P: Patch> Validator for EthereumValidator<'a, P> {
-    fn validate(&mut self) -> bool {
-        let basic = self.validate_basic();
-        let timestamp_and_difficulty = self.validate_timestamp_and_difficulty();
-        let consensus = self.validate_consensus();
-        let gas_limit = self.validate_gas_limit();
-        let state = self.validate_state();
-
-        basic && timestamp_and_difficulty && consensus && gas_limit && state
+    fn validate(&mut self) -> Result<Vec<Receipt>, ValidationError> {
+        self.validate_basic()?;
+        self.validate_timestamp_and_difficulty()?;
+        self.validate_consensus()?;
+        self.validate_gas_limit()?;
+        self.validate_ommers()?;
+        self.validate_state()
     }
 }
 
 impl<'a, P: Patch> EthereumValidator<'a, P> {
-    pub fn new(current_block: &'a Block, parent_header: &'a Header,
-               database: &'a MemoryDatabase, dag: &'a LightDAG<P::Ethash>,
+    pub fn new(current_block: &'a Block, parent_header: &'a Header, spec: &'a ChainSpec,
+               chain: &'a ChainDatabase, database: &'a StateDatabase, dags: &'a DAGCache,
                most_recent_block_hashes: &'a [H256]) -> Self {
-        assert!(dag.is_valid_for(current_block.header.number));
+        assert!(dags.get(current_block.header.number).is_some());
         assert!(U256::from(most_recent_block_hashes.len()) >=
                 min(current_block.header.number, U256::from(256)));
 
         Self {
-            database, dag, current_block, parent_header, most_recent_block_hashes,
+            spec, chain, database, dags, current_block, parent_header, most_recent_block_hashes,
             _marker: PhantomData,
         }
     }
 
-    pub fn validate_consensus(&self) -> bool {
-        let (mix_hash, result) = self.dag.hashimoto(self.current_block.header.partial_hash(),
-                                                    self.current_block.header.nonce);
-        let nonce_value: u64 = self.current_block.header.nonce.into();
-
-        mix_hash == self.current_block.header.mix_hash &&
-            U256::from(nonce_value) <= cross_boundary(self.current_block.header.difficulty)
+    pub fn validate_consensus(&self) -> Result<(), ValidationError> {
+        let header = &self.current_block.header;
+        validate_header_consensus(self.dags.get(header.number).unwrap(), header)
     }
 
-    pub fn validate_basic(&self) -> bool {
-        if self.current_block.header.parent_hash().is_none() {
-            return false;
+    pub fn validate_basic(&self) -> Result<(), ValidationError> {
+        let header = &self.current_block.header;
+
+        match header.parent_hash() {
+            Some(parent_hash) if parent_hash == self.parent_header.header_hash() => (),
+            _ => return Err(ValidationError::InvalidParentHash {
+                expected: header.parent_hash, actual: self.parent_header.header_hash(),
+            }),
+        }
+        if header.number != self.parent_header.number + U256::one() {
+            return Err(ValidationError::InvalidNumber {
+                expected: header.number, actual: self.parent_header.number + U256::one(),
+            });
         }
 
-        let transactions_valid = {
-            let mut transactions_valid = true;
+        let transactions_root = transactions_root(&self.current_block.transactions);
+        if header.transactions_root != transactions_root {
+            return Err(ValidationError::TransactionsRootMismatch {
+                expected: header.transactions_root, actual: transactions_root,
+            });
+        }
+        let ommers_hash = ommers_hash(&self.current_block.ommers);
+        if header.ommers_hash != ommers_hash {
+            return Err(ValidationError::OmmersHashMismatch {
+                expected: header.ommers_hash, actual: ommers_hash,
+            });
+        }
 
-            for transaction in &self.current_block.transactions {
-                transactions_valid = transactions_valid && transaction.is_basic_valid::<P::Signature, P::TransactionValidation>();
+        for (index, transaction) in self.current_block.transactions.iter().enumerate() {
+            if !transaction.is_basic_valid::<P::Signature, P::TransactionValidation>() {
+                return Err(ValidationError::InvalidSignature { index });
             }
+        }
 
-            transactions_valid
-        };
+        Ok(())
+    }
 
-        self.current_block.is_basic_valid() &&
-            transactions_valid &&
-            self.current_block.header.parent_hash().unwrap() == self.parent_header.header_hash() &&
-            self.current_block.header.number == self.parent_header.number + U256::one()
+    pub fn validate_timestamp_and_difficulty(&self) -> Result<(), ValidationError> {
+        validate_header_timestamp_and_difficulty::<P>(&self.current_block.header, self.parent_header)
     }
 
-    pub fn validate_timestamp_and_difficulty(&self) -> bool {
-        self.current_block.header.timestamp > self.parent_header.timestamp &&
-            self.current_block.header.difficulty == calculate_difficulty::<
-                    P::BaseTargetDifficulty, P::DifficultyBomb>(
-                        self.parent_header.difficulty, self.parent_header.timestamp,
-                        self.current_block.header.number, self.current_block.header.timestamp)
+    pub fn validate_gas_limit(&self) -> Result<(), ValidationError> {
+        validate_header_gas_limit(&self.current_block.header, self.parent_header)
     }
 
-    pub fn validate_gas_limit(&self) -> bool {
-        validate_gas_limit(self.parent_header.gas_limit, self.current_block.header.gas_limit)
+    /// Check that every ommer is a valid header whose parent is one
+    /// of the last ancestors, other than the block's own parent, and
+    /// that it is neither an ancestor nor included before.
+    pub fn validate_ommers(&self) -> Result<(), ValidationError> {
+        let ommers = &self.current_block.ommers;
+        if ommers.len() > MAX_OMMERS {
+            return Err(ValidationError::TooManyOmmers {
+                max: MAX_OMMERS, actual: ommers.len(),
+            });
+        }
+        if ommers.len() == 0 {
+            return Ok(());
+        }
+
+        let mut ancestors = HashMap::new();
+        let mut included = HashSet::new();
+        let mut current = Some(self.parent_header.clone());
+        for _ in 0..OMMER_GENERATIONS {
+            let header = match current {
+                Some(header) => header,
+                None => break,
+            };
+            let hash = header.header_hash();
+            if let Some((_, ancestor_ommers)) = self.chain.body(hash) {
+                for ommer in ancestor_ommers {
+                    included.insert(ommer.header_hash());
+                }
+            }
+            current = header.parent_hash().and_then(|parent_hash| self.chain.header(parent_hash));
+            ancestors.insert(hash, header);
+        }
+
+        for (index, ommer) in ommers.iter().enumerate() {
+            let hash = ommer.header_hash();
+            if ancestors.contains_key(&hash) {
+                return Err(ValidationError::OmmerIsAncestor { index });
+            }
+            if !included.insert(hash) {
+                return Err(ValidationError::DuplicateOmmer { index });
+            }
+
+            let parent = match ancestors.get(&ommer.parent_hash) {
+                Some(parent) if ommer.parent_hash != self.parent_header.header_hash() => parent,
+                _ => return Err(ValidationError::UnrelatedOmmer { index }),
+            };
+
+            self.validate_ommer(ommer, parent).map_err(|error| {
+                ValidationError::InvalidOmmer { index, error: Box::new(error) }
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Validate an ommer with the patch active at its own number, which
+    /// differs from the block's right after a transition.
+    fn validate_ommer(&self, ommer: &Header, parent: &Header) -> Result<(), ValidationError> {
+        match self.dags.get(ommer.number) {
+            Some(dag) => validate_header_with_spec(self.spec, ommer, parent, dag),
+            None => Err(ValidationError::MissingDAG { number: ommer.number }),
+        }
     }
 
-    pub fn validate_state(&mut self) -> bool {
+    pub fn validate_state(&mut self) -> Result<Vec<Receipt>, ValidationError> {
         let mut receipts = Vec::new();
         let mut block_logs_bloom = LogsBloom::new();
         let mut block_used_gas = Gas::zero();
 
-        let mut stateful = MemoryStateful::new(self.database, self.parent_header.state_root);
+        let mut stateful = Stateful::new(self.database, self.parent_header.state_root);
 
-        for transaction in &self.current_block.transactions {
+        for (index, transaction) in self.current_block.transactions.iter().enumerate() {
             let valid = match stateful.to_valid::<P::VM>(transaction.clone()) {
                 Ok(val) => val,
-                Err(_) => return false,
+                Err(error) => return Err(ValidationError::InvalidTransaction { index, error }),
             };
             let vm: SeqTransactionVM<P::VM> = stateful.execute(
                 valid, HeaderParams::from(&self.current_block.header), &self.most_recent_block_hashes);
@@ -207,7 +551,7 @@ impl<'a, P: Patch> EthereumValidator<'a, P> {
             }
 
             let receipt = Receipt {
-                used_gas: used_gas.clone(),
+                used_gas: block_used_gas + used_gas,
                 logs,
                 logs_bloom: logs_bloom.clone(),
                 state_root: stateful.root(),
@@ -242,16 +586,38 @@ impl<'a, P: Patch> EthereumValidator<'a, P> {
                 }, HeaderParams::from(&self.current_block.header), &self.most_recent_block_hashes);
         }
 
-        self.current_block.header.state_root == stateful.root() &&
-            self.current_block.header.receipts_root == receipts_root(&receipts) &&
-            self.current_block.header.logs_bloom == block_logs_bloom &&
-            self.current_block.header.gas_used == block_used_gas
+        let header = &self.current_block.header;
+
+        if header.state_root != stateful.root() {
+            return Err(ValidationError::StateRootMismatch {
+                expected: header.state_root, actual: stateful.root(),
+            });
+        }
+        let receipts_root = receipts_root(&receipts);
+        if header.receipts_root != receipts_root {
+            return Err(ValidationError::ReceiptsRootMismatch {
+                expected: header.receipts_root, actual: receipts_root,
+            });
+        }
+        if header.logs_bloom != block_logs_bloom {
+            return Err(ValidationError::LogsBloomMismatch {
+                expected: header.logs_bloom.clone(), actual: block_logs_bloom,
+            });
+        }
+        if header.gas_used != block_used_gas {
+            return Err(ValidationError::GasUsedMismatch {
+                expected: header.gas_used, actual: block_used_gas,
+            });
+        }
+
+        Ok(receipts)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use db::MemoryKeyValueStore;
 
     #[test]
     fn test_calculate_difficulty() {
@@ -266,4 +632,103 @@ mod tests {
                      U256::from(2), 1438270017),
                    U256::from(17163096064usize));
     }
+
+    #[test]
+    fn test_reopen_processor() {
+        let store: Arc<KeyValueStore> = Arc::new(MemoryKeyValueStore::new());
+
+        let genesis_hash = EthereumProcessor::new(store.clone(), ChainSpec::classic()).best_hash();
+        let processor = EthereumProcessor::new(store, ChainSpec::classic());
+        assert_eq!(processor.best_hash(), genesis_hash);
+        assert_eq!(processor.best_header().number, U256::zero());
+    }
+
+    #[test]
+    fn test_put_unknown_parent() {
+        let mut processor = EthereumProcessor::new(Arc::new(MemoryKeyValueStore::new()), ChainSpec::classic());
+
+        let mut header = processor.best_header();
+        header.parent_hash = H256::from(1u64);
+        header.number = U256::one();
+
+        match processor.put(Block { header, transactions: Vec::new(), ommers: Vec::new() }) {
+            Err(ValidationError::UnknownParent(hash)) => assert_eq!(hash, H256::from(1u64)),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_put_header_invalid_number() {
+        let mut processor = EthereumProcessor::new(Arc::new(MemoryKeyValueStore::new()), ChainSpec::classic());
+
+        let mut header = processor.best_header();
+        header.parent_hash = header.header_hash();
+        header.number = U256::from(2u64);
+
+        match processor.put_header(header) {
+            Err(ValidationError::InvalidNumber { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_put_checkpoint() {
+        let genesis = EthereumProcessor::new(Arc::new(MemoryKeyValueStore::new()), ChainSpec::classic())
+            .best_header();
+        let mut header = genesis.clone();
+        header.parent_hash = H256::from(1u64);
+        header.number = U256::from(100u64);
+
+        let mut spec = ChainSpec::classic();
+        spec.checkpoint = Some(Checkpoint {
+            hash: header.header_hash(),
+            number: header.number,
+            total_difficulty: U256::from(1000u64),
+        });
+        let mut processor = EthereumProcessor::new(Arc::new(MemoryKeyValueStore::new()), spec);
+        assert!(processor.pending_checkpoint().is_some());
+
+        match processor.put_checkpoint(genesis) {
+            Err(ValidationError::CheckpointMismatch { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        processor.put_checkpoint(header.clone()).unwrap();
+        assert_eq!(processor.best_hash(), header.header_hash());
+        assert_eq!(processor.best_total_difficulty(), U256::from(1000u64));
+        assert!(processor.pending_checkpoint().is_none());
+    }
+
+    #[test]
+    fn test_validate_ommers() {
+        let processor = EthereumProcessor::new(Arc::new(MemoryKeyValueStore::new()), ChainSpec::classic());
+        let parent = processor.best_header();
+        let mut header = parent.clone();
+        header.parent_hash = parent.header_hash();
+        header.number = U256::one();
+        let hashes = [parent.header_hash()];
+
+        let block = Block { header: header.clone(), transactions: Vec::new(), ommers: vec![parent.clone(); 3] };
+        let validator = EthereumValidator::<FrontierPatch>::new(
+            &block, &parent, &processor.spec, &processor.chain, &processor.database, &processor.dags, &hashes);
+        match validator.validate_ommers() {
+            Err(ValidationError::TooManyOmmers { max: 2, actual: 3 }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let block = Block { header, transactions: Vec::new(), ommers: vec![parent.clone()] };
+        let validator = EthereumValidator::<FrontierPatch>::new(
+            &block, &parent, &processor.spec, &processor.chain, &processor.database, &processor.dags, &hashes);
+        match validator.validate_ommers() {
+            Err(ValidationError::OmmerIsAncestor { index: 0 }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // Only the epoch of the block is generated.
+        let mut distant = parent.clone();
+        distant.number = U256::from(100000u64);
+        match validator.validate_ommer(&distant, &parent) {
+            Err(ValidationError::MissingDAG { number }) => assert_eq!(number, distant.number),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
 }